@@ -0,0 +1,209 @@
+//! Double-click actions (0x13, 0x17)
+//!
+//! Each record can set the action for a double-click and for a
+//! shift-double-click. `perform()` maps the action to the calls of a
+//! `Runner`, which does the actual work (clipboard, browser, ...).
+
+use super::Fail;
+use super::db::Field;
+use super::record;
+use super::run::RunCommand;
+use super::autotype::{self, Key};
+use super::util::read_uint;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    CopyPassword,
+    ViewEdit,
+    AutoType,
+    Browse,
+    CopyNotes,
+    CopyUsername,
+    CopyPasswordMinimize,
+    BrowsePlus,
+    RunCommand,
+    SendEmail,
+    /// Use the application default (0xff)
+    Default,
+    Unknown(u16),
+}
+
+impl Action {
+    /// Decode the 2 byte field value
+    pub fn from_value(val: &[u8]) -> Action {
+        match read_uint(val).unwrap_or(0xff) {
+            0 => Action::CopyPassword,
+            1 => Action::ViewEdit,
+            2 => Action::AutoType,
+            3 => Action::Browse,
+            4 => Action::CopyNotes,
+            5 => Action::CopyUsername,
+            6 => Action::CopyPasswordMinimize,
+            7 => Action::BrowsePlus,
+            8 => Action::RunCommand,
+            9 => Action::SendEmail,
+            0xff => Action::Default,
+            other => Action::Unknown(other as u16),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match *self {
+            Action::CopyPassword => "copy-password",
+            Action::ViewEdit => "view",
+            Action::AutoType => "autotype",
+            Action::Browse => "browse",
+            Action::CopyNotes => "copy-notes",
+            Action::CopyUsername => "copy-username",
+            Action::CopyPasswordMinimize => "copy-password-minimize",
+            Action::BrowsePlus => "browse-autotype",
+            Action::RunCommand => "run",
+            Action::SendEmail => "email",
+            Action::Default => "default",
+            Action::Unknown(_) => "unknown",
+        }
+    }
+
+    /// The record action, the application defaults are copy password
+    /// for double-click and autotype for shift-double-click
+    pub fn from_record(rec: &[Field], shift: bool) -> Action {
+        let typ = if shift { 0x17 } else { 0x13 };
+        let action = record::find(rec, typ)
+            .map(|f| Action::from_value(f.value().as_ref()))
+            .unwrap_or(Action::Default);
+        match action {
+            Action::Default if shift => Action::AutoType,
+            Action::Default => Action::CopyPassword,
+            action => action,
+        }
+    }
+}
+
+/// Performs the actions, e.g. on a desktop or in tests
+pub trait Runner {
+    /// Copy text to the clipboard
+    fn copy(&mut self, text: &[u8]) -> Result<(), Fail>;
+    /// Show the record
+    fn view(&mut self, rec: &[Field]) -> Result<(), Fail>;
+    /// Open an URL (including mailto:)
+    fn open(&mut self, url: &str) -> Result<(), Fail>;
+    /// Execute the run command
+    fn run(&mut self, cmd: &RunCommand, rec: &[Field]) -> Result<(), Fail>;
+    /// Type the keystrokes
+    fn autotype(&mut self, keys: &[Key]) -> Result<(), Fail>;
+}
+
+fn field<'a>(rec: &'a [Field], typ: u8, name: &str) -> Result<&'a [u8], Fail> {
+    record::find(rec, typ)
+        .map(|f| f.value().as_ref())
+        .ok_or_else(|| Fail::MissingField(name.to_owned()))
+}
+
+/// Perform an action for a record
+pub fn perform<R: Runner>(action: Action, rec: &[Field], runner: &mut R) -> Result<(), Fail> {
+    let url = || field(rec, 0x0d, "url").map(|url| String::from_utf8_lossy(url).into_owned());
+    match action {
+        Action::CopyPassword | Action::CopyPasswordMinimize | Action::Default => {
+            runner.copy(field(rec, 0x06, "password")?)
+        }
+        Action::CopyNotes => runner.copy(field(rec, 0x05, "notes")?),
+        Action::CopyUsername => runner.copy(field(rec, 0x04, "username")?),
+        Action::ViewEdit => runner.view(rec),
+        Action::AutoType => runner.autotype(&autotype::from_record(rec)),
+        Action::Browse => runner.open(&url()?),
+        Action::BrowsePlus => {
+            runner.open(&url()?)?;
+            runner.autotype(&autotype::from_record(rec))
+        }
+        Action::RunCommand => {
+            let cmd = field(rec, 0x12, "command")?;
+            runner.run(&RunCommand::parse(&String::from_utf8_lossy(cmd)), rec)
+        }
+        Action::SendEmail => {
+            let email = field(rec, 0x14, "email")?;
+            runner.open(&format!("mailto:{}", String::from_utf8_lossy(email)))
+        }
+        Action::Unknown(val) => Err(Fail::ActionFailed(format!("unknown action {}", val))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Value;
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<String>,
+    }
+
+    impl Runner for Recorder {
+        fn copy(&mut self, text: &[u8]) -> Result<(), Fail> {
+            self.calls.push(format!("copy {}", String::from_utf8_lossy(text)));
+            Ok(())
+        }
+        fn view(&mut self, rec: &[Field]) -> Result<(), Fail> {
+            self.calls.push(format!("view {}", rec.len()));
+            Ok(())
+        }
+        fn open(&mut self, url: &str) -> Result<(), Fail> {
+            self.calls.push(format!("open {}", url));
+            Ok(())
+        }
+        fn run(&mut self, cmd: &RunCommand, rec: &[Field]) -> Result<(), Fail> {
+            self.calls.push(format!("run {}", cmd.masked(rec)));
+            Ok(())
+        }
+        fn autotype(&mut self, keys: &[Key]) -> Result<(), Fail> {
+            self.calls.push(format!("autotype {}", keys.len()));
+            Ok(())
+        }
+    }
+
+    fn field(typ: u8, val: &[u8]) -> Field {
+        Field::from(typ, Value::from(val.to_vec()))
+    }
+
+    #[test]
+    fn test_from_record() {
+        let mut rec = vec![field(0x13, &[3, 0])];
+        assert_eq!(Action::from_record(&rec, false), Action::Browse);
+        assert_eq!(Action::from_record(&rec, true), Action::AutoType);
+        rec.push(field(0x17, &[0xff, 0]));
+        assert_eq!(Action::from_record(&rec, true), Action::AutoType);
+        rec[0] = field(0x13, &[0xff, 0]);
+        assert_eq!(Action::from_record(&rec, false), Action::CopyPassword);
+        assert_eq!(Action::from_value(&[9, 0]), Action::SendEmail);
+        assert_eq!(Action::from_value(&[10, 1]), Action::Unknown(266));
+    }
+
+    #[test]
+    fn test_perform() {
+        let rec = vec![field(0x04, b"me"),
+                       field(0x06, b"secret"),
+                       field(0x0d, b"https://example.com"),
+                       field(0x12, b"ssh $u@host $p"),
+                       field(0x14, b"me@example.com")];
+        let mut runner = Recorder::default();
+        let actions = [Action::CopyPassword,
+                       Action::CopyUsername,
+                       Action::ViewEdit,
+                       Action::BrowsePlus,
+                       Action::RunCommand,
+                       Action::SendEmail];
+        for action in &actions {
+            perform(*action, &rec, &mut runner).unwrap();
+        }
+        assert_eq!(runner.calls,
+                   vec!["copy secret",
+                        "copy me",
+                        "view 5",
+                        "open https://example.com",
+                        "autotype 5",
+                        "run ssh me@host ********",
+                        "open mailto:me@example.com"]);
+
+        assert!(perform(Action::CopyNotes, &rec, &mut runner).is_err());
+        assert!(perform(Action::Unknown(42), &rec, &mut runner).is_err());
+    }
+}
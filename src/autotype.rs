@@ -0,0 +1,311 @@
+//! Autotype (0x0e) keystroke sequences
+//!
+//! The autotype field holds the text PasswordSafe types into a window,
+//! with codes for the record fields and special keys
+//!
+//! - `\u` username, `\p` password, `\g` group, `\i` title, `\l` url,
+//!   `\m` email, `\o` notes
+//! - `\t` tab, `\s` shift+tab, `\n` enter, `\b` backspace, `\\` a backslash
+//! - `\d<ms>` delay between keystrokes, `\w<ms>` and `\W<s>` wait
+//! - `\z` use the alternative typing method
+//! - `\{key}` press a named key, e.g. `\{Escape}`
+//!
+//! An empty autotype field types username, tab, password, tab, enter.
+//! Unknown codes are typed as they are.
+
+use super::Fail;
+use super::db::Field;
+use super::record;
+
+/// Default autotype sequence, used if the record has none
+pub const DEFAULT: &str = "\\u\\t\\p\\t\\n";
+
+/// Default delay between keystrokes (ms)
+const DEFAULT_DELAY: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum Key {
+    /// Literal text
+    Text(String),
+    /// The value of a record field, see `expand()`
+    Field(u8),
+    Tab,
+    ShiftTab,
+    Enter,
+    Backspace,
+    /// A key by name
+    #[serde(rename = "key")]
+    Named(String),
+    /// Set the delay between keystrokes (ms)
+    Delay(u32),
+    /// Wait before the next keystroke (ms)
+    Wait(u32),
+    Alternate,
+}
+
+/// Typing tools supported by `script()`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    Xdotool,
+    Ydotool,
+    Wtype,
+}
+
+/// Parse an autotype string into a keystroke list
+pub fn parse(s: &str) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut text = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+
+        let key = match chars.peek().cloned() {
+            Some('u') => Key::Field(0x04),
+            Some('p') => Key::Field(0x06),
+            Some('g') => Key::Field(0x02),
+            Some('i') => Key::Field(0x03),
+            Some('l') => Key::Field(0x0d),
+            Some('m') => Key::Field(0x14),
+            Some('o') => Key::Field(0x05),
+            Some('t') => Key::Tab,
+            Some('s') => Key::ShiftTab,
+            Some('n') => Key::Enter,
+            Some('b') => Key::Backspace,
+            Some('z') => Key::Alternate,
+            Some('\\') => Key::Text("\\".to_owned()),
+            Some(code @ 'd') | Some(code @ 'w') | Some(code @ 'W') => {
+                let mut rest = chars.clone();
+                rest.next();
+                let digits: String = rest.take_while(|c| c.is_ascii_digit()).collect();
+                match digits.parse::<u32>() {
+                    Ok(n) => {
+                        for _ in 0..digits.len() {
+                            chars.next();
+                        }
+                        match code {
+                            'd' => Key::Delay(n),
+                            'w' => Key::Wait(n),
+                            _ => Key::Wait(n.saturating_mul(1000)),
+                        }
+                    }
+                    Err(_) => {
+                        text.push(c);
+                        continue;
+                    }
+                }
+            }
+            Some('{') => {
+                let rest: String = chars.clone().skip(1).collect();
+                match rest.find('}') {
+                    Some(end) if end > 0 => {
+                        for _ in 0..rest[..end].chars().count() + 1 {
+                            chars.next();
+                        }
+                        Key::Named(rest[..end].to_owned())
+                    }
+                    _ => {
+                        text.push(c);
+                        continue;
+                    }
+                }
+            }
+            _ => {
+                text.push(c);
+                continue;
+            }
+        };
+        chars.next();
+
+        if let Key::Text(ref s) = key {
+            text.push_str(s);
+            continue;
+        }
+        if !text.is_empty() {
+            keys.push(Key::Text(text.clone()));
+            text.clear();
+        }
+        keys.push(key);
+    }
+    if !text.is_empty() {
+        keys.push(Key::Text(text));
+    }
+    keys
+}
+
+/// The keystroke list for a record, the record fields are replaced
+/// with their values
+pub fn from_record(rec: &[Field]) -> Vec<Key> {
+    let keys = match record::find(rec, 0x0e) {
+        Some(f) => parse(&String::from_utf8_lossy(f.value().as_ref())),
+        None => parse(DEFAULT),
+    };
+    expand(&keys, rec)
+}
+
+/// Replace `Key::Field` with the field text from the record, missing
+/// fields are empty
+pub fn expand(keys: &[Key], rec: &[Field]) -> Vec<Key> {
+    let mut out: Vec<Key> = Vec::new();
+    for key in keys {
+        let key = match *key {
+            Key::Field(typ) => {
+                match record::find(rec, typ) {
+                    Some(f) => Key::Text(String::from_utf8_lossy(f.value().as_ref()).into_owned()),
+                    None => continue,
+                }
+            }
+            ref key => key.clone(),
+        };
+        if let Key::Text(ref s) = key {
+            if let Some(Key::Text(prev)) = out.last_mut() {
+                prev.push_str(s);
+                continue;
+            }
+        }
+        out.push(key);
+    }
+    out
+}
+
+/// Quote a string for sh
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Linux input event codes for ydotool
+fn keycode(name: &str) -> Option<u32> {
+    let code = match name {
+        "Escape" => 1,
+        "BackSpace" => 14,
+        "Tab" => 15,
+        "Return" => 28,
+        "space" => 57,
+        "Home" => 102,
+        "Up" => 103,
+        "Prior" => 104,
+        "Left" => 105,
+        "Right" => 106,
+        "End" => 107,
+        "Down" => 108,
+        "Next" => 109,
+        "Insert" => 110,
+        "Delete" => 111,
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// A shell script that types the keystrokes with the given tool. Text is
+/// piped through stdin, so the values are not part of the tool's argv.
+/// Use `expand()` before calling this, any field keys are ignored.
+pub fn script(keys: &[Key], tool: Tool) -> Result<String, Fail> {
+    let mut out = String::from("#!/bin/sh\n");
+    let mut delay = DEFAULT_DELAY;
+
+    for key in keys {
+        let name = match *key {
+            Key::Text(ref s) => {
+                let cmd = match tool {
+                    Tool::Xdotool => format!("xdotool type --delay {} --file -", delay),
+                    Tool::Ydotool => format!("ydotool type --key-delay {} --file -", delay),
+                    Tool::Wtype => format!("wtype -d {} -", delay),
+                };
+                out.push_str(&format!("printf '%s' {} | {}\n", sh_quote(s), cmd));
+                continue;
+            }
+            Key::Delay(ms) => {
+                delay = ms;
+                continue;
+            }
+            Key::Wait(ms) => {
+                out.push_str(&format!("sleep {}.{:03}\n", ms / 1000, ms % 1000));
+                continue;
+            }
+            Key::Field(_) | Key::Alternate => continue,
+            Key::Tab => "Tab",
+            Key::ShiftTab => "shift+Tab",
+            Key::Enter => "Return",
+            Key::Backspace => "BackSpace",
+            Key::Named(ref name) => name,
+        };
+
+        let cmd = match tool {
+            Tool::Xdotool => format!("xdotool key --delay {} {}", delay, sh_quote(name)),
+            Tool::Wtype => {
+                match name.rfind('+') {
+                    Some(pos) => {
+                        let modifier = sh_quote(&name[..pos]);
+                        format!("wtype -M {} -k {} -m {}", modifier, sh_quote(&name[pos + 1..]), modifier)
+                    }
+                    None => format!("wtype -k {}", sh_quote(name)),
+                }
+            }
+            Tool::Ydotool => {
+                let (shift, name) = match name.strip_prefix("shift+") {
+                    Some(name) => (true, name),
+                    None => (false, name),
+                };
+                let code = keycode(name).ok_or_else(|| Fail::UnsupportedKey(name.to_owned()))?;
+                if shift {
+                    format!("ydotool key --key-delay {} 42:1 {}:1 {}:0 42:0", delay, code, code)
+                } else {
+                    format!("ydotool key --key-delay {} {}:1 {}:0", delay, code, code)
+                }
+            }
+        };
+        out.push_str(&cmd);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Value;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(DEFAULT),
+                   vec![Key::Field(0x04), Key::Tab, Key::Field(0x06), Key::Tab, Key::Enter]);
+        assert_eq!(parse("a\\\\b\\u\\d250\\W2\\w\\{Escape}\\{x\\z\\q"),
+                   vec![Key::Text("a\\b".to_owned()),
+                        Key::Field(0x04),
+                        Key::Delay(250),
+                        Key::Wait(2000),
+                        Key::Text("\\w".to_owned()),
+                        Key::Named("Escape".to_owned()),
+                        Key::Text("\\{x".to_owned()),
+                        Key::Alternate,
+                        Key::Text("\\q".to_owned())]);
+        assert_eq!(parse(""), vec![]);
+        assert_eq!(parse("\\"), vec![Key::Text("\\".to_owned())]);
+    }
+
+    #[test]
+    fn test_expand() {
+        let rec = vec![Field::from(0x04, Value::from(b"me".to_vec()))];
+        assert_eq!(expand(&parse("x\\uy\\p\\n"), &rec),
+                   vec![Key::Text("xmey".to_owned()), Key::Enter]);
+    }
+
+    #[test]
+    fn test_script() {
+        let keys = vec![Key::Text("it's".to_owned()), Key::Delay(50), Key::ShiftTab, Key::Wait(1500)];
+        assert_eq!(script(&keys, Tool::Xdotool).unwrap(),
+                   "#!/bin/sh\n\
+                    printf '%s' 'it'\\''s' | xdotool type --delay 10 --file -\n\
+                    xdotool key --delay 50 'shift+Tab'\n\
+                    sleep 1.500\n");
+        assert_eq!(script(&keys[1..3], Tool::Ydotool).unwrap(),
+                   "#!/bin/sh\nydotool key --key-delay 50 42:1 15:1 15:0 42:0\n");
+        assert_eq!(script(&keys[2..3], Tool::Wtype).unwrap(),
+                   "#!/bin/sh\nwtype -M 'shift' -k 'Tab' -m 'shift'\n");
+        assert!(script(&[Key::Named("F13".to_owned())], Tool::Ydotool).is_err());
+    }
+}
@@ -5,24 +5,43 @@ extern crate uuid;
 extern crate rust_base58;
 extern crate chrono;
 extern crate rpassword;
-extern crate strfmt;
 extern crate gpgagent;
 extern crate dirs;
+extern crate serde_json;
+extern crate libc;
 
-use pwx::{PwxReader, Field, Value};
+use pwx::{PwxReader, Field, Value, Fail};
+use pwx::filter::Filter;
+use pwx::record;
+use pwx::recid;
+use pwx::query;
+use pwx::rank;
+use pwx::table;
+use pwx::output;
+use pwx::template::{Template, Var};
+use pwx::run::RunCommand;
+use pwx::autotype::{self, Key};
+use pwx::action::{self, Action, Runner};
+use pwx::totp::Totp;
+use pwx::card::{self, CreditCard};
 use std::io::{Write, stderr};
-use std::process::exit;
-use std::path::PathBuf;
+use std::process::{exit, Command, ExitStatus, Stdio};
+use std::io;
+use std::env;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
+use std::cmp::{Ordering, Reverse};
 use docopt::Docopt;
 use uuid::Uuid;
-use rust_base58::{ToBase58, FromBase58};
-use pwx::util::{fuzzy_eq, from_time_t};
+use rust_base58::ToBase58;
+use pwx::util::{fuzzy_match, fold_case, from_time_t, parse_recent, format_recent, default_username,
+                generate_password, format_relative, read_uint, parse_when, parse_duration};
 use std::str::from_utf8;
-use chrono::Local;
+use chrono::{DateTime, FixedOffset, Local, Utc, SecondsFormat};
 use chrono::Duration;
 use std::env::current_dir;
-use strfmt::Format;
 
 // Get pkg version at compile time
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
@@ -31,24 +50,99 @@ const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 struct Args {
     arg_file: String,
     arg_fieldname: String,
-    arg_recid: String,
+    arg_recid: Vec<String>,
+    arg_group: String,
     arg_keyword: Vec<String>,
     arg_fmt: String,
+    arg_uri: String,
     flag_url: String,
     flag_group: String,
     flag_password_age: u32,
     flag_username: String,
     flag_title: String,
+    flag_saved_filter: String,
+    flag_where: String,
+    flag_created_since: String,
+    flag_created_before: String,
+    flag_changed_since: String,
+    flag_changed_before: String,
+    flag_accessed_since: String,
+    flag_accessed_before: String,
+    flag_modified_since: String,
+    flag_modified_before: String,
+    flag_expires_within: String,
+    flag_expires_before: String,
+    flag_ignore_accents: bool,
+    flag_rank: bool,
+    flag_limit: Option<usize>,
+    flag_sort: String,
+    flag_reverse: bool,
+    flag_columns: String,
+    flag_within: Option<u32>,
+    flag_expired: bool,
     flag_long: bool,
     flag_fmt: String,
+    flag_output: String,
     flag_quiet: bool,
+    flag_update_recent: bool,
+    flag_dry_run: bool,
+    flag_stdin: bool,
+    flag_shift: bool,
+    flag_email: String,
+    flag_notes_file: String,
+    flag_generate: bool,
+    flag_set: Vec<String>,
+    flag_unset: Vec<String>,
+    flag_emit: String,
+    flag_yes: bool,
+    flag_unlink: bool,
+    flag_reveal: bool,
+    flag_json: bool,
+    flag_utc: bool,
     cmd_list: bool,
+    cmd_recent: bool,
+    cmd_filters: bool,
+    cmd_expiring: bool,
+    cmd_protect: bool,
+    cmd_unprotect: bool,
+    cmd_run: bool,
+    cmd_autotype: bool,
+    cmd_otp: bool,
+    cmd_set: bool,
+    cmd_cards: bool,
+    cmd_open: bool,
+    cmd_add: bool,
+    cmd_edit: bool,
+    cmd_show: bool,
+    cmd_rm: bool,
+    cmd_mv: bool,
     cmd_get: bool,
     cmd_getrec: bool,
     cmd_info: bool,
     flag_version: bool,
 }
 
+impl Args {
+    /// The record id, for commands that take a single record
+    fn recid(&self) -> &str {
+        self.arg_recid.first().map(|s| s.as_str()).unwrap_or("")
+    }
+
+    /// The --output format, `show --json` is `--output json`
+    fn output(&self) -> output::Format {
+        if self.flag_json {
+            return output::Format::Json;
+        }
+        match output::Format::from_name(&self.flag_output) {
+            Some(format) => format,
+            None => {
+                let _ = writeln!(stderr(), "Invalid output format: {}", self.flag_output);
+                exit(-1);
+            }
+        }
+    }
+}
+
 /// Convert path to absolute path
 pub fn abspath(p: &PathBuf) -> Result<PathBuf, std::io::Error> {
     if p.is_absolute() {
@@ -97,11 +191,12 @@ impl<'a> KeywordFilter<'a> {
         };
 
         if let Ok(s) = utf8 {
+            let accents = self.args.flag_ignore_accents;
             for (idx, word) in self.args
                                    .arg_keyword
                                    .iter()
                                    .enumerate() {
-                self.m_filter[idx] = self.m_filter[idx] || fuzzy_eq(&word, s);
+                self.m_filter[idx] = self.m_filter[idx] || fuzzy_match(word, s, accents);
             }
         }
     }
@@ -112,28 +207,155 @@ impl<'a> KeywordFilter<'a> {
     }
 }
 
+/// A date filter, e.g. --modified-since. The time field (None for the
+/// password expiry) must be in [after, before).
+struct DateFilter {
+    typ: Option<u8>,
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+}
+
+/// The date filters given on the command line
+fn date_filters(args: &Args, now: &DateTime<Utc>) -> Vec<DateFilter> {
+    let when = |option: &str, val: &str| {
+        if val.is_empty() {
+            return None;
+        }
+        match parse_when(val, now) {
+            Ok(ts) => Some(ts),
+            Err(err) => {
+                let _ = writeln!(stderr(), "{}: {}", option, err);
+                exit(-1);
+            }
+        }
+    };
+    let mut filters = vec![
+        DateFilter {
+            typ: Some(0x07),
+            after: when("--created-since", &args.flag_created_since),
+            before: when("--created-before", &args.flag_created_before),
+        },
+        DateFilter {
+            typ: Some(0x08),
+            after: when("--changed-since", &args.flag_changed_since),
+            before: when("--changed-before", &args.flag_changed_before),
+        },
+        DateFilter {
+            typ: Some(0x09),
+            after: when("--accessed-since", &args.flag_accessed_since),
+            before: when("--accessed-before", &args.flag_accessed_before),
+        },
+        DateFilter {
+            typ: Some(0x0c),
+            after: when("--modified-since", &args.flag_modified_since),
+            before: when("--modified-before", &args.flag_modified_before),
+        },
+        DateFilter {
+            typ: None,
+            after: None,
+            before: when("--expires-before", &args.flag_expires_before),
+        },
+    ];
+    if !args.flag_expires_within.is_empty() {
+        match parse_duration(&args.flag_expires_within).and_then(|d| now.checked_add_signed(d)) {
+            Some(ts) => filters.push(DateFilter { typ: None, after: Some(*now), before: Some(ts) }),
+            None => {
+                let _ = writeln!(stderr(), "--expires-within: not a duration: {}", args.flag_expires_within);
+                exit(-1);
+            }
+        }
+    }
+    filters.retain(|f| f.after.is_some() || f.before.is_some());
+    filters
+}
+
+/// Returns true if the record matches all date filters, fails if a time
+/// field of the record is malformed
+fn match_dates(filters: &[DateFilter], record: &[Field]) -> Result<bool, Fail> {
+    for filter in filters {
+        let ts = match filter.typ {
+            Some(typ) => record::try_time(record, typ)?,
+            None => {
+                // The expiry is computed from these
+                for typ in &[0x0a, 0x08, 0x07] {
+                    record::try_time(record, *typ)?;
+                }
+                record::expiry(record)
+            }
+        };
+        let matched = match ts {
+            Some(ts) => filter.after.is_none_or(|t| ts >= t) && filter.before.is_none_or(|t| ts < t),
+            None => false,
+        };
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Report a record that cannot be filtered
+fn warn_record(record: &[Field], err: &Fail) {
+    let id = record::uuid(record).map(|u| recid::base58(&u)).unwrap_or_default();
+    let _ = writeln!(stderr(), "Warning: skipping record {}: {}", id, err);
+}
+
 /// Query the database for records that match the given filters
 ///
-/// The function f(recid, recdict) is called for each match. If
+/// The function f(recid, recdict, record) is called for each match. If
 /// it returns true, then the query stops.
 fn foreach_record<F>(p: &mut PwxReader, args: &Args, mut f: F)
-        where F: FnMut(Field, HashMap<String, String>) -> bool {
+        where F: FnMut(Field, HashMap<String, String>, &[Field]) -> bool {
     let min_pw_age = Duration::days(args.flag_password_age as i64);
+    let accents = args.flag_ignore_accents;
+    let saved_filter = if args.flag_saved_filter.is_empty() {
+        None
+    } else {
+        Some(saved_filter(p, &args.flag_saved_filter))
+    };
+    let query = if args.flag_where.is_empty() {
+        None
+    } else {
+        match query::parse(&args.flag_where) {
+            Ok(expr) => Some(expr),
+            Err(err) => {
+                let _ = writeln!(stderr(), "{}", err);
+                exit(-1);
+            }
+        }
+    };
+    let now = Utc::now();
+    let date_filters = date_filters(args, &now);
 
     for record in p.records().unwrap() {
         let record = record.expect("Error while reading database");
         let mut recid = None;
 
+        if let Some(ref filter) = saved_filter {
+            if !filter.expr.matches(&record, &now) {
+                continue;
+            }
+        }
+        if let Some(ref expr) = query {
+            if !expr.matches(&record, &now) {
+                continue;
+            }
+        }
+        match match_dates(&date_filters, &record) {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(err) => {
+                warn_record(&record, &err);
+                continue;
+            }
+        }
+
         // Field filters
         let mut f_username = args.flag_username.is_empty();
         let mut f_title = args.flag_title.is_empty();
         let mut f_url = args.flag_url.is_empty();
         let mut f_group = args.flag_group.is_empty();
 
-        // The password-age filter can use either ctime or ptime
-        let mut creation_time = None;
-        let mut password_age = None;
-
         // Keyword filters
         let mut f_keywords = KeywordFilter::new(&args);
 
@@ -142,52 +364,55 @@ fn foreach_record<F>(p: &mut PwxReader, args: &Args, mut f: F)
         // format strings to work
         recdict.insert("title".to_owned(), String::new());
         recdict.insert("username".to_owned(), String::new());
+        if let Some(uuid) = record::uuid(&record) {
+            recdict.insert("id".to_owned(), recid::base58(&uuid));
+        }
 
-        for field in record {
+        for field in &record {
             if let Some(name) = field.name() {
-                match field {
+                match *field {
                     // The UUID field should respect --long
                     Field::Uuid(ref val) => if args.flag_long {
                         recdict.insert(name.to_owned(), format!("{}", field));
                     } else {
                         recdict.insert(name.to_owned(), val.as_ref().to_base58());
                     },
+                    _ if is_time(field.typ()) => {
+                        let ts = from_time_t(field.value().as_ref()).unwrap_or_default();
+                        recdict.insert(name.to_owned(), format_time(args, &ts));
+                    }
                     _ => {
                         recdict.insert(name.to_owned(), format!("{}", field));
                     }
                 }
             }
 
-            f_keywords.push(&field);
-            match field {
+            if let Field::TwoFactorKey(_) = *field {
+                if let Some(Ok(totp)) = Totp::from_record(&record) {
+                    recdict.insert("otp".to_owned(), totp.code(now.timestamp()));
+                }
+            }
+
+            f_keywords.push(field);
+            match *field {
                 Field::Uuid(_) => {
                     recid = Some(field.clone());
                 }
                 Field::Title(_) => {
                     let title = format!("{}", field);
-                    f_title = f_title || fuzzy_eq(&args.flag_title, &title);
+                    f_title = f_title || fuzzy_match(&args.flag_title, &title, accents);
                 }
                 Field::Username(_) => {
                     let username = format!("{}", field);
-                    f_username = f_username || fuzzy_eq(&args.flag_username, &username);
+                    f_username = f_username || fuzzy_match(&args.flag_username, &username, accents);
                 }
                 Field::Url(_) => {
                     let url = format!("{}", field);
-                    f_url = f_url || fuzzy_eq(&args.flag_url, &url);
+                    f_url = f_url || fuzzy_match(&args.flag_url, &url, accents);
                 }
                 Field::Group(_) => {
                     let group = format!("{}", field);
-                    f_group = f_group || fuzzy_eq(&args.flag_group, &group);
-                }
-                Field::CreationTime(val) => {
-                    let ts = from_time_t(val.as_ref()).unwrap();
-                    let diff = Local::now().naive_local() - ts;
-                    creation_time = Some(diff);
-                }
-                Field::PasswordModificationTime(val) => {
-                    let ts = from_time_t(val.as_ref()).unwrap();
-                    let diff = Local::now().naive_local() - ts;
-                    password_age = Some(diff);
+                    f_group = f_group || fuzzy_match(&args.flag_group, &group, accents);
                 }
                 _ => (),
             }
@@ -198,94 +423,1486 @@ fn foreach_record<F>(p: &mut PwxReader, args: &Args, mut f: F)
             continue;
         }
 
-        match (creation_time, password_age) {
-            // No fields were found treat as a match
-            (None, None) => (),
-            // Password was never modified
-            (Some(diff), None) if diff < min_pw_age => continue,
-            // Password modification time
-            (_, Some(diff)) if diff < min_pw_age => continue,
-            _ => (),
+        if args.flag_password_age > 0 {
+            // The password age counts from the last change, or the creation.
+            // Records without either match.
+            let changed = match record::try_time(&record, 0x08) {
+                Ok(None) => record::try_time(&record, 0x07),
+                other => other,
+            };
+            match changed {
+                Ok(Some(ts)) if now - ts < min_pw_age => continue,
+                Ok(_) => (),
+                Err(err) => {
+                    warn_record(&record, &err);
+                    continue;
+                }
+            }
         }
 
-        if !f_keywords.matched() {
+        // With --rank the keywords are matched by pwx::rank instead
+        if !args.flag_rank && !f_keywords.matched() {
             // Skip, generic keyword filter did not match
             continue;
         }
 
         if let Some(uuid) = recid {
-            if f(uuid, recdict) {
+            if f(uuid, recdict, &record) {
                 break;
             }
         }
     }
 }
 
+/// Find a filter saved in the database by name
+fn saved_filter(p: &mut PwxReader, name: &str) -> Filter {
+    let filters = match p.filters() {
+        Ok(filters) => filters,
+        Err(err) => {
+            let _ = writeln!(stderr(), "Error reading saved filters: {}", err);
+            exit(-1);
+        }
+    };
+    match filters.into_iter().find(|f| f.name == name) {
+        Some(filter) => filter,
+        None => {
+            let _ = writeln!(stderr(), "Unknown saved filter: {}", name);
+            exit(-1);
+        }
+    }
+}
+
+/// List the names of the filters saved in the database
+fn cmd_filters(p: &mut PwxReader) {
+    match p.filters() {
+        Ok(filters) => {
+            for filter in filters {
+                println!("{}", filter.name);
+            }
+        }
+        Err(err) => {
+            let _ = writeln!(stderr(), "Error reading saved filters: {}", err);
+            exit(-1);
+        }
+    }
+}
+
+/// The --fmt format string, or the default list format. The default
+/// format marks protected records.
+fn list_fmt(args: &Args, protected: bool) -> &str {
+    if !args.flag_fmt.is_empty() {
+        &args.flag_fmt
+    } else if protected {
+        "{uuid} {title} [{username}] (protected)\n"
+    } else {
+        "{uuid} {title} [{username}]\n"
+    }
+}
+
+/// Print text output, with --output nul the trailing newline is replaced
+/// by NUL
+fn print_text(args: &Args, text: &str) {
+    if args.output() == output::Format::Nul {
+        print!("{}\0", text.strip_suffix('\n').unwrap_or(text));
+    } else {
+        print!("{}", text);
+    }
+}
+
+/// Print JSON objects as json, jsonl, csv or tsv. CSV and TSV have a
+/// header line and the given columns. Unless `single`, json is an array.
+fn print_objects(format: output::Format, columns: &[&str], objects: &[serde_json::Value], single: bool) {
+    match format {
+        output::Format::Json if single => {
+            println!("{}", serde_json::to_string_pretty(&objects[0]).expect("output json"))
+        }
+        output::Format::Json => println!("{}", serde_json::to_string_pretty(objects).expect("output json")),
+        output::Format::Jsonl => {
+            for obj in objects {
+                println!("{}", obj);
+            }
+        }
+        output::Format::Csv | output::Format::Tsv => {
+            let row: fn(&[String]) -> String = if format == output::Format::Csv {
+                output::csv_row
+            } else {
+                output::tsv_row
+            };
+            let header: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+            println!("{}", row(&header));
+            for obj in objects {
+                let cells: Vec<String> = columns.iter().map(|c| output::cell(obj, c)).collect();
+                println!("{}", row(&cells));
+            }
+        }
+        output::Format::Text | output::Format::Nul => unreachable!("not a structured format"),
+    }
+}
+
+/// Returns true for time fields
+fn is_time(typ: u8) -> bool {
+    matches!(typ, 0x07..=0x0a | 0x0c | 0x24)
+}
+
+/// A time as shown to the user, in the local time zone unless --utc
+fn user_time(args: &Args, ts: &DateTime<Utc>) -> DateTime<FixedOffset> {
+    if args.flag_utc {
+        ts.fixed_offset()
+    } else {
+        ts.with_timezone(&Local).fixed_offset()
+    }
+}
+
+/// Format a time for humans, see `user_time()`
+fn format_time(args: &Args, ts: &DateTime<Utc>) -> String {
+    user_time(args, ts).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// The value of a format string variable, record fields keep their type
+/// so that e.g. `{ctime|date:%Y}` works
+fn fmt_var(args: &Args, name: &str, recdict: &HashMap<String, String>, record: &[Field]) -> Option<Var> {
+    let text = recdict.get(name)?;
+    let var = match record.iter().find(|f| f.name() == Some(name)) {
+        Some(field) if field.typ() == 0x01 => {
+            Var::Uuid(record::uuid(record)?, text.clone())
+        }
+        Some(field) if is_time(field.typ()) => {
+            Var::Time(user_time(args, &from_time_t(field.value().as_ref()).ok()?))
+        }
+        _ => Var::Text(text.clone()),
+    };
+    Some(var)
+}
+
+/// Apply a format string to a record
+fn apply_fmt(args: &Args, fmt: &str, recdict: &HashMap<String, String>, record: &[Field])
+        -> Result<String, Fail> {
+    Template::parse(fmt)?.render(|name| fmt_var(args, name, recdict, record))
+}
+
+/// Print a record using a format string
+fn print_fmt(args: &Args, fmt: &str, recdict: &HashMap<String, String>, record: &[Field]) {
+    match apply_fmt(args, fmt, recdict, record) {
+        Ok(s) => print_text(args, &s),
+        Err(err @ Fail::InvalidTemplate(_)) => {
+            let _ = writeln!(stderr(), "{}", err);
+            exit(-1);
+        }
+        Err(err) => {
+            let _ = writeln!(stderr(), "Error applying fmt string: {}", err);
+        }
+    }
+}
+
+/// Keys for `list --sort`
+const SORT_KEYS: &[&str] = &["title", "group", "username", "ctime", "ptime", "atime", "expiry"];
+
+/// Default columns for `list` on a terminal
+const DEFAULT_COLUMNS: &str = "uuid,title,username,group";
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Text(String),
+    Time(DateTime<Utc>),
+}
+
+/// The sort key of a record, text is case folded
+fn sort_key(rec: &[Field], key: &str) -> Option<SortKey> {
+    let text = |typ| {
+        record::find(rec, typ)
+            .map(|f| SortKey::Text(fold_case(&String::from_utf8_lossy(f.value().as_ref()), false)))
+    };
+    match key {
+        "title" => text(0x03),
+        "group" => text(0x02),
+        "username" => text(0x04),
+        "ctime" => record::time(rec, 0x07).map(SortKey::Time),
+        "ptime" => record::time(rec, 0x08).map(SortKey::Time),
+        "atime" => record::time(rec, 0x09).map(SortKey::Time),
+        "expiry" => record::expiry(rec).map(SortKey::Time),
+        _ => None,
+    }
+}
+
+/// Returns true if stdout is a terminal
+fn stdout_is_tty() -> bool {
+    unsafe { libc::isatty(1) != 0 }
+}
+
+/// The terminal width, from $COLUMNS or the terminal, 80 if unknown
+fn terminal_width() -> usize {
+    if let Some(cols) = env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        return cols;
+    }
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+        return size.ws_col as usize;
+    }
+    80
+}
+
 fn cmd_list(p: &mut PwxReader, args: &Args) {
+    if !args.flag_sort.is_empty() && !SORT_KEYS.contains(&args.flag_sort.as_str()) {
+        let _ = writeln!(stderr(), "Invalid sort key {}, expected one of {}", args.flag_sort,
+                         SORT_KEYS.join(", "));
+        exit(-1);
+    }
+    let columns: Vec<&str> = if args.flag_columns.is_empty() {
+        DEFAULT_COLUMNS.split(',').collect()
+    } else {
+        args.flag_columns.split(',').map(|c| c.trim()).collect()
+    };
+    for column in &columns {
+        if Field::typ_from_name(column).is_none() && *column != "otp" && *column != "id" {
+            let _ = writeln!(stderr(), "Unknown column: {}", column);
+            exit(-1);
+        }
+    }
+
+    let mut entries = Vec::new();
+    foreach_record(p, args, |_, recdict, record| {
+        let score = if args.flag_rank {
+            match rank::score_record(record, &args.arg_keyword, args.flag_ignore_accents) {
+                Some(score) => score,
+                None => return false,
+            }
+        } else {
+            0
+        };
+        let key = sort_key(record, &args.flag_sort);
+        entries.push((score, key, recdict, record::is_protected(record), record.to_vec()));
+        false
+    });
+
+    // Best match first, records with the same score keep the file order
+    if args.flag_rank {
+        entries.sort_by_key(|e| Reverse(e.0));
+    }
+    // Records without the sort key are listed last
+    if !args.flag_sort.is_empty() {
+        entries.sort_by(|a, b| {
+            match (&a.1, &b.1) {
+                (Some(x), Some(y)) if args.flag_reverse => y.cmp(x),
+                (Some(x), Some(y)) => x.cmp(y),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        });
+    } else if args.flag_reverse {
+        entries.reverse();
+    }
+    entries.truncate(args.flag_limit.unwrap_or(usize::MAX));
+
+    let format = args.output();
+    if format.is_structured() {
+        let columns = if args.flag_columns.is_empty() {
+            output::columns(args.flag_reveal)
+        } else {
+            columns
+        };
+        let objects: Vec<_> = entries.iter()
+            .map(|e| output::record_json(&e.4, args.flag_reveal))
+            .collect();
+        print_objects(format, &columns, &objects, false);
+        return;
+    }
+    if !args.flag_fmt.is_empty() || format == output::Format::Nul ||
+       (args.flag_columns.is_empty() && !stdout_is_tty()) {
+        for (_, _, recdict, protected, record) in &entries {
+            print_fmt(args, list_fmt(args, *protected), recdict, record);
+        }
+        return;
+    }
+
+    let rows: Vec<Vec<String>> = entries.iter()
+        .map(|(_, _, recdict, _, _)| {
+            columns.iter().map(|c| recdict.get(*c).cloned().unwrap_or_default()).collect()
+        })
+        .collect();
+    if stdout_is_tty() {
+        let headers: Vec<String> = columns.iter().map(|c| c.to_uppercase()).collect();
+        print!("{}", table::render(&headers, &rows, terminal_width()));
+    } else {
+        for row in rows {
+            println!("{}", row.join("\t"));
+        }
+    }
+}
+
+/// List records in the recently used entries list, most recent first
+fn cmd_recent(p: &mut PwxReader, args: &Args) {
+    let recent = match p.info() {
+        Ok(info) => info.recent,
+        Err(err) => {
+            let _ = writeln!(stderr(), "Error reading database header: {}", err);
+            exit(-1);
+        }
+    };
+
+    let mut matches = HashMap::new();
+    foreach_record(p, args, |recid, recdict, record| {
+        matches.insert(recid.value().as_ref().to_vec(), (recdict, record.to_vec()));
+        false
+    });
+    let recent: Vec<_> = recent.iter().filter_map(|uuid| matches.get(&uuid.as_bytes()[..])).collect();
+
+    let format = args.output();
+    if format.is_structured() {
+        let objects: Vec<_> = recent.iter()
+            .map(|(_, record)| output::record_json(record, args.flag_reveal))
+            .collect();
+        print_objects(format, &output::columns(args.flag_reveal), &objects, false);
+        return;
+    }
+    for (recdict, record) in recent {
+        print_fmt(args, list_fmt(args, record::is_protected(record)), recdict, record);
+    }
+}
+
+/// List records with a password expiry time, soonest first
+fn cmd_expiring(p: &mut PwxReader, args: &Args) {
     let fmt = if args.flag_fmt.is_empty() {
-        "{uuid} {title} [{username}]\n"
+        "{uuid}\t{expires}\t{days}\t{title}\n"
     } else {
         &args.flag_fmt
     };
-    foreach_record(p, args, |_, recdict| {
-        match fmt.format(&recdict) {
-            Ok(s) => {
-                print!("{}", s);
-            }
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error applying fmt string: {}", err);
+    let now = Utc::now();
+
+    let mut matches = Vec::new();
+    foreach_record(p, args, |_, mut recdict, record| {
+        let expires = match record::expiry(record) {
+            Some(ts) => ts,
+            None => return false,
+        };
+        let expired = expires <= now;
+        let soon = match args.flag_within {
+            Some(days) => !expired && expires <= now + Duration::days(days as i64),
+            None => false,
+        };
+        let all = !args.flag_expired && args.flag_within.is_none();
+        if all || soon || (args.flag_expired && expired) {
+            let expires_at = user_time(args, &expires).to_rfc3339_opts(SecondsFormat::Secs, true);
+            recdict.insert("expires".to_owned(), expires_at);
+            recdict.insert("days".to_owned(), (expires - now).num_days().to_string());
+            matches.push((expires, recdict, record.to_vec()));
+        }
+        false
+    });
+
+    matches.sort_by_key(|m| m.0);
+    let format = args.output();
+    if format.is_structured() {
+        let objects: Vec<_> = matches.iter()
+            .map(|(expires, _, record)| {
+                let mut obj = output::record_json(record, args.flag_reveal);
+                obj["expires"] = serde_json::Value::from(expires.to_rfc3339_opts(SecondsFormat::Secs, true));
+                obj["days"] = serde_json::Value::from((*expires - now).num_days());
+                obj
+            })
+            .collect();
+        let mut columns = output::columns(args.flag_reveal);
+        columns.extend(&["expires", "days"]);
+        print_objects(format, &columns, &objects, false);
+        return;
+    }
+    for (_, recdict, record) in matches {
+        print_fmt(args, fmt, &recdict, &record);
+    }
+}
+
+/// List the records with a credit card, with the card status: ok,
+/// expiring (within --within DAYS, 30 by default), expired or unknown
+fn cmd_cards(p: &mut PwxReader, args: &Args) {
+    let fmt = if args.flag_fmt.is_empty() {
+        "{uuid}\t{cc_number}\t{cc_expiry}\t{status}\t{title}\n"
+    } else {
+        &args.flag_fmt
+    };
+    let today = Local::now().date_naive();
+    let soon = today + Duration::days(args.flag_within.unwrap_or(30) as i64);
+    let format = args.output();
+
+    let mut objects = Vec::new();
+    foreach_record(p, args, |_, mut recdict, record| {
+        let card = match CreditCard::from_record(record) {
+            Some(card) => card,
+            None => return false,
+        };
+        let id = recdict.get("uuid").cloned().unwrap_or_default();
+        if !card.is_valid() {
+            let _ = writeln!(stderr(), "Warning: invalid card number in record {}", id);
+        }
+        let status = match card.expiry() {
+            Some(expiry) if expiry < today => "expired",
+            Some(expiry) if expiry <= soon => "expiring",
+            Some(_) => "ok",
+            None => {
+                if card.expiration.is_some() {
+                    let _ = writeln!(stderr(), "Warning: invalid card expiration in record {}", id);
+                }
+                "unknown"
             }
+        };
+        if format.is_structured() {
+            let mut obj = output::record_json(record, args.flag_reveal);
+            obj["status"] = serde_json::Value::from(status);
+            objects.push(obj);
+            return false;
         }
+        recdict.entry("cc_expiry".to_owned()).or_default();
+        recdict.insert("status".to_owned(), status.to_owned());
+        print_fmt(args, fmt, &recdict, record);
         false
     });
+    if format.is_structured() {
+        let mut columns = output::columns(args.flag_reveal);
+        columns.push("status");
+        print_objects(format, &columns, &objects, false);
+    }
 }
 
-fn cmd_get(p: &mut PwxReader, args: &Args) {
-    // Try decoding as base58
-    let bin = match args.arg_recid.from_base58() {
-        Ok(vec) => vec,
-        Err(_) => {
-            Uuid::parse_str(&args.arg_recid)
-                .expect("Invalid record id")
-                .as_bytes()
-                .to_vec()
+/// Move a record to the front of the recently used entries list
+/// (header field 0x0f) and save the database
+fn update_recent(p: &mut PwxReader, uuid: Uuid) -> Result<(), Fail> {
+    let mut header = p.header()?;
+    let records = p.records()?.collect::<Result<Vec<_>, _>>()?;
+
+    let mut recent = header.iter()
+        .find(|&&(typ, _)| typ == 0x0f)
+        .and_then(|(_, val)| parse_recent(val.as_ref()))
+        .unwrap_or_default();
+    recent.retain(|u| *u != uuid);
+    recent.insert(0, uuid);
+
+    let val = Value::from(format_recent(&recent).into_bytes());
+    match header.iter_mut().find(|&&mut (typ, _)| typ == 0x0f) {
+        Some(field) => field.1 = val,
+        None => header.push((0x0f, val)),
+    }
+    p.save(&header, &records)
+}
+
+/// Read the header and all records, to modify and save the database
+fn read_all(p: &mut PwxReader) -> (Vec<(u8, Value)>, Vec<Vec<Field>>) {
+    let header = p.header().and_then(|header| {
+        let records = p.records()?.collect::<Result<Vec<_>, _>>()?;
+        Ok((header, records))
+    });
+    match header {
+        Ok(db) => db,
+        Err(err) => {
+            let _ = writeln!(stderr(), "Error while reading database: {}", err);
+            exit(-1);
+        }
+    }
+}
+
+/// Save the database, exits on error
+fn save_all(p: &mut PwxReader, header: &[(u8, Value)], records: &[Vec<Field>]) {
+    if let Err(err) = p.save(header, records) {
+        let _ = writeln!(stderr(), "Error saving database: {}", err);
+        exit(-1);
+    }
+}
+
+/// Find the index of a record by id, prefix or path (see `pwx::recid`),
+/// exits if there is no such record or the id is ambiguous
+fn find_record(records: &[Vec<Field>], recid: &str) -> usize {
+    match recid::resolve(records, recid) {
+        Ok(idx) => idx,
+        Err(err) => {
+            let _ = writeln!(stderr(), "{}", err);
+            exit(-1);
+        }
+    }
+}
+
+/// Set or clear the protected entry flag of a record
+fn cmd_protect(p: &mut PwxReader, args: &Args, protected: bool) {
+    let (header, mut records) = read_all(p);
+    let idx = find_record(&records, args.recid());
+    record::set_protected(&mut records[idx], protected);
+    save_all(p, &header, &records);
+}
+
+/// Expand and execute the run command (field 0x12) of a record
+fn cmd_run(p: &mut PwxReader, args: &Args) {
+    let (_, records) = read_all(p);
+    let idx = find_record(&records, args.recid());
+    let rec = &records[idx];
+
+    let cmd = match record::find(rec, 0x12) {
+        Some(f) => RunCommand::parse(&String::from_utf8_lossy(f.value().as_ref())),
+        None => {
+            let _ = writeln!(stderr(), "Record has no run command: {}", args.recid());
+            exit(-1);
         }
     };
-    let get_uuid = Field::Uuid(Value::from(bin));
 
-    foreach_record(p, args, |recid, recdict| {
-        if get_uuid == recid {
-            if args.cmd_get {
-                match recdict.get(&args.arg_fieldname) {
-                    Some(f) => {
-                        println!("{}", f);
-                        exit(0);
+    if args.flag_dry_run {
+        println!("{}", cmd.masked(rec));
+        return;
+    }
+
+    match exec_run(&cmd, rec, args.flag_stdin) {
+        Ok(status) => exit(status.code().unwrap_or(-1)),
+        Err(err) => {
+            let _ = writeln!(stderr(), "Unable to run command: {}", err);
+            exit(-1);
+        }
+    }
+}
+
+/// Spawn a command and wait for it, with data written to its stdin
+fn exec_with_stdin(child: &mut Command, data: Option<&[u8]>) -> io::Result<ExitStatus> {
+    if data.is_some() {
+        child.stdin(Stdio::piped());
+    }
+    let mut child = child.spawn()?;
+    if let (Some(mut stdin), Some(data)) = (child.stdin.take(), data) {
+        stdin.write_all(data)?;
+    }
+    child.wait()
+}
+
+/// Execute a run command with `sh -c`, the variables are passed as
+/// environment variables. If stdin is set the password is written to
+/// the command's stdin.
+fn exec_run(cmd: &RunCommand, rec: &[Field], stdin: bool) -> io::Result<ExitStatus> {
+    let mut child = Command::new("sh");
+    child.arg("-c").arg(cmd.sh_command()).env_remove("PWX_PASSWORD");
+    for (name, val) in cmd.env(rec) {
+        child.env(name, String::from_utf8_lossy(val.unsecure()).as_ref());
+    }
+
+    let mut password = record::find(rec, 0x06).map(|f| f.value().as_ref().to_vec()).unwrap_or_default();
+    password.push(b'\n');
+    exec_with_stdin(&mut child, if stdin { Some(&password) } else { None })
+}
+
+/// Performs the double-click actions with desktop tools. The clipboard
+/// and browser commands can be set with PWX_CLIPBOARD and PWX_BROWSER.
+/// The view is the same as `pwx show` for the record `idx`.
+struct DesktopRunner<'a> {
+    args: &'a Args,
+    records: &'a [Vec<Field>],
+    idx: usize,
+}
+
+impl<'a> DesktopRunner<'a> {
+    fn wayland() -> bool {
+        env::var_os("WAYLAND_DISPLAY").is_some()
+    }
+
+    fn check(status: io::Result<ExitStatus>, what: &str) -> Result<(), Fail> {
+        match status {
+            Ok(ref status) if status.success() => Ok(()),
+            Ok(status) => Err(Fail::ActionFailed(format!("{} exited with {}", what, status))),
+            Err(err) => Err(Fail::ActionFailed(format!("{}: {}", what, err))),
+        }
+    }
+}
+
+impl<'a> Runner for DesktopRunner<'a> {
+    fn copy(&mut self, text: &[u8]) -> Result<(), Fail> {
+        let cmd = env::var("PWX_CLIPBOARD").unwrap_or_else(|_| {
+            if Self::wayland() { "wl-copy" } else { "xclip -selection clipboard" }.to_owned()
+        });
+        let status = exec_with_stdin(Command::new("sh").arg("-c").arg(&cmd), Some(text));
+        Self::check(status, &cmd)
+    }
+
+    fn view(&mut self, _rec: &[Field]) -> Result<(), Fail> {
+        // Secrets are masked unless --reveal, see show_record()
+        print_show(&show_record(self.args, self.records, self.idx));
+        Ok(())
+    }
+
+    fn open(&mut self, url: &str) -> Result<(), Fail> {
+        let cmd = env::var("PWX_BROWSER").unwrap_or_else(|_| "xdg-open".to_owned());
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", cmd))
+            .arg("pwx")
+            .arg(url)
+            .status();
+        Self::check(status, &cmd)
+    }
+
+    fn run(&mut self, cmd: &RunCommand, rec: &[Field]) -> Result<(), Fail> {
+        Self::check(exec_run(cmd, rec, false), "run command")
+    }
+
+    fn autotype(&mut self, keys: &[Key]) -> Result<(), Fail> {
+        let tool = if Self::wayland() { autotype::Tool::Wtype } else { autotype::Tool::Xdotool };
+        let script = autotype::script(keys, tool)?;
+        let status = exec_with_stdin(&mut Command::new("sh"), Some(script.as_bytes()));
+        Self::check(status, "autotype")
+    }
+}
+
+/// Read the password for a new record, from the terminal or from the
+/// first line of stdin
+fn read_new_password() -> String {
+    if unsafe { libc::isatty(0) } == 0 {
+        let mut line = String::new();
+        if let Err(err) = io::stdin().read_line(&mut line) {
+            let _ = writeln!(stderr(), "Unable to read password: {}", err);
+            exit(-1);
+        }
+        return line.trim_end_matches(['\r', '\n']).to_owned();
+    }
+
+    let password = rpassword::prompt_password_stderr("New password: ")
+        .expect("Unable to read password from console");
+    let confirm = rpassword::prompt_password_stderr("Confirm password: ")
+        .expect("Unable to read password from console");
+    if password != confirm {
+        let _ = writeln!(stderr(), "Passwords do not match");
+        exit(-1);
+    }
+    password
+}
+
+/// Add a new record, and print its id
+fn cmd_add(p: &mut PwxReader, args: &Args) {
+    let (header, mut records) = read_all(p);
+
+    let password = if args.flag_generate {
+        generate_password(20)
+    } else {
+        read_new_password()
+    };
+    if password.is_empty() {
+        let _ = writeln!(stderr(), "The password is empty");
+        exit(-1);
+    }
+
+    let notes = if args.flag_notes_file.is_empty() {
+        String::new()
+    } else {
+        match fs::read_to_string(&args.flag_notes_file) {
+            Ok(notes) => notes,
+            Err(err) => {
+                let _ = writeln!(stderr(), "Unable to read {}: {}", args.flag_notes_file, err);
+                exit(-1);
+            }
+        }
+    };
+
+    let username = if args.flag_username.is_empty() {
+        header.iter()
+            .find(|&&(typ, _)| typ == 0x02)
+            .and_then(|(_, prefs)| default_username(&String::from_utf8_lossy(prefs.as_ref())))
+            .unwrap_or_default()
+    } else {
+        args.flag_username.clone()
+    };
+
+    let mut rec = record::new(&Utc::now()).expect("current time before the epoch");
+    let fields = [(0x02, &args.flag_group),
+                  (0x03, &args.flag_title),
+                  (0x04, &username),
+                  (0x05, &notes),
+                  (0x06, &password),
+                  (0x0d, &args.flag_url),
+                  (0x14, &args.flag_email)];
+    for &(typ, val) in &fields {
+        if !val.is_empty() {
+            rec.push(Field::from(typ, Value::from(val.as_bytes().to_vec())));
+        }
+    }
+
+    let uuid = record::uuid(&rec).expect("new record uuid");
+    records.push(rec);
+    save_all(p, &header, &records);
+
+    println!("{}", format_recid(args, &uuid));
+}
+
+/// The field type for an editable field name, exits if there is none
+fn editable_field(name: &str) -> u8 {
+    match Field::typ_from_name(name) {
+        Some(typ) if record::is_editable(typ) => typ,
+        Some(_) => {
+            let _ = writeln!(stderr(), "Field cannot be edited: {}", name);
+            exit(-1);
+        }
+        None => {
+            let _ = writeln!(stderr(), "Unknown field: {}", name);
+            exit(-1);
+        }
+    }
+}
+
+/// Change a record field, an empty or missing value removes the field.
+/// Returns true if the record changed.
+fn edit_field(rec: &mut Vec<Field>, typ: u8, text: Option<&str>, now: &DateTime<Utc>)
+              -> Result<bool, Fail> {
+    let text = text.filter(|t| !t.is_empty());
+    let current = record::find(rec, typ).and_then(record::to_text);
+    if current.as_deref() == text {
+        return Ok(false);
+    }
+    match text {
+        Some(text) if typ == 0x06 => record::set_password(rec, text.as_bytes(), now)?,
+        Some(text) => record::set_field(rec, record::from_text(typ, text)?)?,
+        None => record::remove_field(rec, typ)?,
+    }
+    Ok(true)
+}
+
+/// Create a file only readable by the user
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Overwrite a file with zeros and remove it
+fn wipe(path: &Path) -> io::Result<()> {
+    let len = fs::metadata(path)?.len();
+    let mut f = OpenOptions::new().write(true).open(path)?;
+    f.write_all(&vec![0; len as usize])?;
+    f.sync_all()?;
+    fs::remove_file(path)
+}
+
+/// The record as a text document, for $EDITOR
+fn edit_document(recid: &str, rec: &[Field]) -> String {
+    let mut doc = format!("# Editing record {}\n\
+                           # Remove a line to remove the field, lines starting with a space\n\
+                           # continue the value of the previous line. Times are UTC.\n", recid);
+    let names: Vec<String> = (0..=0xff)
+        .filter(|typ| record::is_editable(*typ))
+        .filter_map(|typ| Field::from(typ, Value::from(vec![])).name().map(str::to_owned))
+        .collect();
+    doc.push_str(&format!("# Fields: {}\n", names.join(", ")));
+
+    for field in rec {
+        if let (Some(name), Some(text)) = (field.name(), record::to_text(field)) {
+            doc.push_str(&format!("{}: {}\n", name, text.replace('\n', "\n ")));
+        }
+    }
+    doc
+}
+
+/// Parse the document from `edit_document()` into (type, value) pairs
+fn parse_document(doc: &str) -> Result<Vec<(u8, String)>, String> {
+    let mut fields: Vec<(u8, String)> = Vec::new();
+    for line in doc.lines() {
+        if line.starts_with('#') || line.trim().is_empty() && !line.starts_with(' ') {
+            continue;
+        }
+        if let Some(cont) = line.strip_prefix(' ') {
+            match fields.last_mut() {
+                Some(&mut (_, ref mut val)) => {
+                    val.push('\n');
+                    val.push_str(cont);
+                }
+                None => return Err(format!("Unexpected line: {}", line)),
+            }
+            continue;
+        }
+
+        let (name, val) = line.split_once(':').ok_or_else(|| format!("Invalid line: {}", line))?;
+        let typ = match Field::typ_from_name(name.trim()) {
+            Some(typ) if record::is_editable(typ) => typ,
+            _ => return Err(format!("Unknown field: {}", name)),
+        };
+        let val = val.strip_prefix(' ').unwrap_or(val).to_owned();
+        fields.retain(|&(t, _)| t != typ);
+        fields.push((typ, val));
+    }
+    Ok(fields)
+}
+
+/// Open the record in $VISUAL or $EDITOR, the file is created in
+/// $XDG_RUNTIME_DIR or /dev/shm to keep it out of the disk
+fn edit_in_editor(recid: &str, rec: &[Field]) -> Vec<(u8, String)> {
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None if Path::new("/dev/shm").is_dir() => PathBuf::from("/dev/shm"),
+        None => {
+            let _ = writeln!(stderr(), "Warning: no tmpfs found, using {}", env::temp_dir().display());
+            env::temp_dir()
+        }
+    };
+    let path = dir.join(format!("pwx-edit-{}.txt", std::process::id()));
+
+    let written = create_private(&path).and_then(|mut f| f.write_all(edit_document(recid, rec).as_bytes()));
+    if let Err(err) = written {
+        let _ = writeln!(stderr(), "Unable to create {}: {}", path.display(), err);
+        let _ = wipe(&path);
+        exit(-1);
+    }
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned());
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("pwx")
+        .arg(&path)
+        .env_remove("PWX_PASSWORD")
+        .status();
+    let doc = fs::read_to_string(&path);
+    if let Err(err) = wipe(&path) {
+        let _ = writeln!(stderr(), "Warning: unable to remove {}: {}", path.display(), err);
+    }
+
+    match (status, doc) {
+        (Ok(ref status), Ok(ref doc)) if status.success() => {
+            parse_document(doc).unwrap_or_else(|err| {
+                let _ = writeln!(stderr(), "{}, the record was not changed", err);
+                exit(-1);
+            })
+        }
+        _ => {
+            let _ = writeln!(stderr(), "Editor failed, the record was not changed");
+            exit(-1);
+        }
+    }
+}
+
+/// Edit the fields of a record, with --set/--unset or in $EDITOR
+fn cmd_edit(p: &mut PwxReader, args: &Args) {
+    let (header, mut records) = read_all(p);
+    let idx = find_record(&records, args.recid());
+    let now = Utc::now();
+
+    let mut changes: Vec<(u8, Option<String>)> = Vec::new();
+    if args.flag_set.is_empty() && args.flag_unset.is_empty() {
+        let fields = edit_in_editor(args.recid(), &records[idx]);
+        for field in &records[idx] {
+            let typ = field.typ();
+            if record::is_editable(typ) && !fields.iter().any(|&(t, _)| t == typ) {
+                changes.push((typ, None));
+            }
+        }
+        changes.extend(fields.into_iter().map(|(typ, val)| (typ, Some(val))));
+    } else {
+        for set in &args.flag_set {
+            match set.split_once('=') {
+                Some((name, val)) => changes.push((editable_field(name), Some(val.to_owned()))),
+                None => {
+                    let _ = writeln!(stderr(), "Invalid --set, expected name=value: {}", set);
+                    exit(-1);
+                }
+            }
+        }
+        for name in &args.flag_unset {
+            changes.push((editable_field(name), None));
+        }
+    }
+
+    let rec = &mut records[idx];
+    let mut changed = false;
+    for (typ, val) in changes {
+        match edit_field(rec, typ, val.as_deref(), &now) {
+            Ok(c) => changed = changed || c,
+            Err(err) => {
+                let _ = writeln!(stderr(), "{}", err);
+                exit(-1);
+            }
+        }
+    }
+    if changed {
+        if let Err(err) = record::touch(rec, &now) {
+            let _ = writeln!(stderr(), "{}", err);
+            exit(-1);
+        }
+        save_all(p, &header, &records);
+    }
+}
+
+/// Format a record id, as an UUID with --long or as base58
+fn format_recid(args: &Args, uuid: &Uuid) -> String {
+    if args.flag_long {
+        uuid.hyphenated().to_string()
+    } else {
+        uuid.as_bytes().to_base58()
+    }
+}
+
+/// A short description of a record, title and username
+fn describe(rec: &[Field]) -> String {
+    let text = |typ| record::find(rec, typ).map(|f| format!("{}", f)).unwrap_or_default();
+    format!("{} [{}]", text(0x03), text(0x04))
+}
+
+/// Ask a yes/no question on stderr, the answer is read from stdin
+fn confirm(question: &str) -> bool {
+    let _ = write!(stderr(), "{} [y/N] ", question);
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(_) => matches!(line.trim(), "y" | "Y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+/// Delete records, after confirmation. Aliases and shortcuts of the
+/// deleted records are reported, and with --unlink converted into normal
+/// entries.
+fn cmd_rm(p: &mut PwxReader, args: &Args) {
+    let (mut header, mut records) = read_all(p);
+
+    let mut deleted: Vec<usize> = Vec::new();
+    for recid in &args.arg_recid {
+        let idx = find_record(&records, recid);
+        if !deleted.contains(&idx) {
+            deleted.push(idx);
+        }
+    }
+    for &idx in &deleted {
+        if let Err(err) = record::check_protected(&records[idx]) {
+            let _ = writeln!(stderr(), "{}: {}", describe(&records[idx]), err);
+            exit(-1);
+        }
+    }
+    let uuids: Vec<Uuid> = deleted.iter().filter_map(|&idx| record::uuid(&records[idx])).collect();
+
+    // Aliases and shortcuts that would be left without their base entry
+    let mut links: Vec<(usize, usize)> = Vec::new();
+    for (idx, rec) in records.iter().enumerate() {
+        if deleted.contains(&idx) {
+            continue;
+        }
+        if let Some(link) = record::link(rec) {
+            if let Some(base) = uuids.iter().position(|u| *u == link.base()) {
+                links.push((idx, deleted[base]));
+            }
+        }
+    }
+    for &(idx, base) in &links {
+        let kind = match record::link(&records[idx]) {
+            Some(record::Link::Shortcut(_)) => "Shortcut",
+            _ => "Alias",
+        };
+        let id = record::uuid(&records[idx]).map(|u| format_recid(args, &u)).unwrap_or_default();
+        let _ = writeln!(stderr(), "{} {} {} refers to {}", kind, id, describe(&records[idx]),
+                         describe(&records[base]));
+    }
+    if !links.is_empty() && !args.flag_unlink {
+        let _ = writeln!(stderr(), "Use --unlink to convert them into normal entries");
+    }
+
+    if !args.flag_yes {
+        for &idx in &deleted {
+            let _ = writeln!(stderr(), "{}", describe(&records[idx]));
+        }
+        if !confirm(&format!("Delete {} record(s)?", deleted.len())) {
+            exit(-1);
+        }
+    }
+
+    if args.flag_unlink {
+        let now = Utc::now();
+        for &(idx, base) in &links {
+            let base = records[base].clone();
+            let rec = &mut records[idx];
+            if let Err(err) = record::unlink(rec, &base).and_then(|_| record::touch(rec, &now)) {
+                let _ = writeln!(stderr(), "{}: {}", describe(rec), err);
+                exit(-1);
+            }
+        }
+    }
+
+    // Deleted records are also removed from the recently used entries
+    if let Some(field) = header.iter_mut().find(|&&mut (typ, _)| typ == 0x0f) {
+        if let Some(mut recent) = parse_recent(field.1.as_ref()) {
+            recent.retain(|u| !uuids.contains(u));
+            field.1 = Value::from(format_recent(&recent).into_bytes());
+        }
+    }
+
+    let mut idx = 0;
+    records.retain(|_| {
+        idx += 1;
+        !deleted.contains(&(idx - 1))
+    });
+    save_all(p, &header, &records);
+}
+
+/// Move records to a group, or with -G rename a group and its subgroups
+fn cmd_mv(p: &mut PwxReader, args: &Args) {
+    let (header, mut records) = read_all(p);
+    let now = Utc::now();
+
+    let mut moved = 0;
+    if args.flag_group.is_empty() {
+        for recid in &args.arg_recid {
+            let idx = find_record(&records, recid);
+            let rec = &mut records[idx];
+            if let Err(err) = record::set_group(rec, &args.arg_group).and_then(|_| record::touch(rec, &now)) {
+                let _ = writeln!(stderr(), "{}: {}", describe(rec), err);
+                exit(-1);
+            }
+            moved += 1;
+        }
+    } else {
+        for rec in &mut records {
+            let renamed = record::rename_group(rec, &args.flag_group, &args.arg_group)
+                .and_then(|renamed| {
+                    if renamed {
+                        record::touch(rec, &now)?;
                     }
-                    None => {
-                        let _ = writeln!(stderr(), "Record has no field: {}", args.arg_fieldname);
-                        exit(-1);
+                    Ok(renamed)
+                });
+            match renamed {
+                Ok(true) => moved += 1,
+                Ok(false) => (),
+                Err(err) => {
+                    let _ = writeln!(stderr(), "{}: {}", describe(rec), err);
+                    exit(-1);
+                }
+            }
+        }
+        if moved == 0 {
+            let _ = writeln!(stderr(), "No records in group: {}", args.flag_group);
+            exit(-1);
+        }
+    }
+    save_all(p, &header, &records);
+    if !args.flag_quiet {
+        let _ = writeln!(stderr(), "Moved {} record(s)", moved);
+    }
+}
+
+/// Sections of `pwx show`, with the field types in display order. Other
+/// fields are shown at the end.
+const SHOW_SECTIONS: &[(&str, &[u8])] = &[
+    ("Entry", &[0x01, 0x02, 0x03, 0x04, 0x06, 0x0d, 0x14, 0x05]),
+    ("Times", &[0x07, 0x08, 0x0c, 0x09, 0x0a, 0x11]),
+    ("Two-factor", &[0x1b, 0x21, 0x22, 0x23, 0x24]),
+    ("Credit card", &[0x1c, 0x1d, 0x1e, 0x1f]),
+    ("Actions", &[0x0e, 0x12, 0x13, 0x17]),
+    ("Other", &[0x15]),
+];
+
+#[derive(serde::Serialize)]
+struct ShowField {
+    section: &'static str,
+    #[serde(rename = "type")]
+    typ: u8,
+    name: Option<String>,
+    label: String,
+    value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relative: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct ShowHistoryEntry {
+    time: String,
+    relative: String,
+    password: String,
+}
+
+#[derive(serde::Serialize)]
+struct ShowHistory {
+    active: bool,
+    max: usize,
+    entries: Vec<ShowHistoryEntry>,
+}
+
+#[derive(serde::Serialize)]
+struct ShowLink {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+    title: Option<String>,
+    username: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct ShowRecord {
+    id: String,
+    fields: Vec<ShowField>,
+    history: Option<ShowHistory>,
+    link: Option<ShowLink>,
+}
+
+const MASK: &str = "********";
+
+/// Format a time for humans, or as RFC 3339 (UTC) for structured output
+fn show_time(args: &Args, ts: &DateTime<Utc>) -> String {
+    if args.output().is_structured() {
+        ts.to_rfc3339_opts(SecondsFormat::Secs, true)
+    } else {
+        format_time(args, ts)
+    }
+}
+
+/// Collect the fields of a record for `pwx show`
+fn show_record(args: &Args, records: &[Vec<Field>], idx: usize) -> ShowRecord {
+    let rec = &records[idx];
+    let now = Utc::now();
+    let link = record::link(rec);
+    let base = link.and_then(|link| {
+        records.iter().find(|r| record::uuid(r) == Some(link.base()))
+    });
+
+    let mut fields = Vec::new();
+    for field in rec {
+        let typ = field.typ();
+        if typ == 0x0f {
+            // Shown as the password history
+            continue;
+        }
+        let section = SHOW_SECTIONS.iter()
+            .find(|&&(_, types)| types.contains(&typ))
+            .map(|&(name, _)| name)
+            .unwrap_or("Other");
+        let val = field.value().as_ref();
+        let text = || String::from_utf8_lossy(val).into_owned();
+
+        let mut relative = None;
+        let value = match typ {
+            0x01 => record::uuid(rec).map(|u| format_recid(args, &u)).unwrap_or_default(),
+            0x06 if args.flag_reveal => {
+                match base.and_then(|b| record::find(b, 0x06)) {
+                    Some(password) => String::from_utf8_lossy(password.value().as_ref()).into_owned(),
+                    None => text(),
+                }
+            }
+            0x06 | 0x1b | 0x1e | 0x1f if !args.flag_reveal => MASK.to_owned(),
+            0x1c if !args.flag_reveal => card::mask(&text()),
+            0x07..=0x0a | 0x0c | 0x24 => {
+                match from_time_t(val) {
+                    Ok(ts) => {
+                        relative = Some(format_relative(&ts, &now));
+                        show_time(args, &ts)
                     }
+                    Err(_) => text(),
                 }
-            } else {
-                match args.arg_fmt.format(&recdict) {
-                    Ok(s) => {
-                        print!("{}", s);
-                        exit(0);
+            }
+            0x11 => format!("{} days", read_uint(val).unwrap_or(0)),
+            0x15 => if record::is_protected(rec) { "yes" } else { "no" }.to_owned(),
+            0x0e => text(),
+            _ if field.label().is_some() => format!("{}", field),
+            _ => val.iter().map(|b| format!("{:02x}", b)).collect(),
+        };
+        let label = match field.label() {
+            Some(label) => label.to_owned(),
+            None if typ == 0x0e => "Autotype".to_owned(),
+            None => format!("Field 0x{:02x}", typ),
+        };
+        let name = field.name()
+            .map(|n| n.to_owned())
+            .or_else(|| if typ == 0x0e { Some("autotype".to_owned()) } else { None });
+        fields.push(ShowField { section, typ, name, label, value, relative });
+    }
+
+    let order = |f: &ShowField| {
+        SHOW_SECTIONS.iter()
+            .enumerate()
+            .flat_map(|(i, &(_, types))| types.iter().position(|t| *t == f.typ).map(|j| (i, j)))
+            .next()
+            .unwrap_or((SHOW_SECTIONS.len(), f.typ as usize))
+    };
+    fields.sort_by_key(|f| order(f));
+
+    let history = record::history(rec).map(|history| {
+        ShowHistory {
+            active: history.active,
+            max: history.max,
+            entries: history.entries
+                .iter()
+                .rev()
+                .map(|(ts, password)| {
+                    ShowHistoryEntry {
+                        time: show_time(args, ts),
+                        relative: format_relative(ts, &now),
+                        password: if args.flag_reveal { password.clone() } else { MASK.to_owned() },
+                    }
+                })
+                .collect(),
+        }
+    });
+
+    let link = link.map(|link| {
+        let text = |typ| {
+            base.and_then(|b| record::find(b, typ))
+                .map(|f| String::from_utf8_lossy(f.value().as_ref()).into_owned())
+        };
+        ShowLink {
+            kind: match link {
+                record::Link::Alias(_) => "alias",
+                record::Link::Shortcut(_) => "shortcut",
+            },
+            id: format_recid(args, &link.base()),
+            title: text(0x03),
+            username: text(0x04),
+        }
+    });
+
+    ShowRecord {
+        id: record::uuid(rec).map(|u| format_recid(args, &u)).unwrap_or_default(),
+        fields,
+        history,
+        link,
+    }
+}
+
+/// Print all fields of a record
+fn cmd_show(p: &mut PwxReader, args: &Args) {
+    let (_, records) = read_all(p);
+    let idx = find_record(&records, args.recid());
+    let show = show_record(args, &records, idx);
+
+    let format = args.output();
+    match format {
+        output::Format::Csv | output::Format::Tsv => {
+            // One row per field
+            let objects: Vec<_> = show.fields
+                .iter()
+                .map(|f| {
+                    serde_json::json!({"section": f.section, "name": f.name, "label": f.label, "value": f.value})
+                })
+                .collect();
+            print_objects(format, &["section", "name", "label", "value"], &objects, false);
+        }
+        output::Format::Json | output::Format::Jsonl => {
+            let obj = serde_json::to_value(&show).expect("show json");
+            print_objects(format, &[], &[obj], true);
+        }
+        output::Format::Text | output::Format::Nul => print_show(&show),
+    }
+}
+
+/// Print a record for `pwx show`, by section
+fn print_show(show: &ShowRecord) {
+    let mut section = "";
+    for field in &show.fields {
+        if field.section != section {
+            if !section.is_empty() {
+                println!();
+            }
+            section = field.section;
+            println!("{}", section);
+        }
+        let mut lines = field.value.lines();
+        let value = match field.relative {
+            Some(ref relative) => format!("{} ({})", field.value, relative),
+            None => lines.next().unwrap_or("").to_owned(),
+        };
+        println!("  {:<27} {}", field.label, value);
+        if field.relative.is_none() {
+            for line in lines {
+                println!("  {:<27} {}", "", line);
+            }
+        }
+        if field.typ == 0x06 {
+            if let Some(ref link) = show.link {
+                let target = match link.title {
+                    Some(ref title) => {
+                        format!("{} [{}] {}", title, link.username.as_deref().unwrap_or(""), link.id)
                     }
-                    Err(err) => {
-                        let _ = writeln!(stderr(), "Error applying fmt string: {}", err);
-                        exit(-1);
+                    None => format!("{} (missing)", link.id),
+                };
+                let label = if link.kind == "alias" { "Alias of" } else { "Shortcut to" };
+                println!("  {:<27} {}", label, target);
+            }
+        }
+    }
+
+    if let Some(ref history) = show.history {
+        println!();
+        let state = if history.active { "kept" } else { "not kept" };
+        println!("Password history ({}, max {})", state, history.max);
+        for entry in &history.entries {
+            println!("  {} ({})  {}", entry.time, entry.relative, entry.password);
+        }
+    }
+}
+
+/// Perform the double-click (or shift-double-click) action of a record
+fn cmd_open(p: &mut PwxReader, args: &Args) {
+    let (_, records) = read_all(p);
+    let idx = find_record(&records, args.recid());
+    let action = Action::from_record(&records[idx], args.flag_shift);
+    let mut runner = DesktopRunner { args, records: &records, idx };
+    if let Err(err) = action::perform(action, &records[idx], &mut runner) {
+        let _ = writeln!(stderr(), "{}", err);
+        exit(-1);
+    }
+}
+
+/// Print the autotype sequence of a record as a script for a typing tool
+fn cmd_autotype(p: &mut PwxReader, args: &Args) {
+    let (_, records) = read_all(p);
+    let idx = find_record(&records, args.recid());
+    let keys = autotype::from_record(&records[idx]);
+
+    let tool = match args.flag_emit.as_str() {
+        "json" => {
+            println!("{}", serde_json::to_string(&keys).expect("autotype json"));
+            return;
+        }
+        "xdotool" => autotype::Tool::Xdotool,
+        "ydotool" => autotype::Tool::Ydotool,
+        "wtype" => autotype::Tool::Wtype,
+        other => {
+            let _ = writeln!(stderr(), "Unknown autotype tool: {}", other);
+            exit(-1);
+        }
+    };
+    match autotype::script(&keys, tool) {
+        Ok(script) => print!("{}", script),
+        Err(err) => {
+            let _ = writeln!(stderr(), "{}", err);
+            exit(-1);
+        }
+    }
+}
+
+/// Print the current TOTP code of a record, or set its TOTP parameters
+/// from an otpauth:// URI
+fn cmd_otp(p: &mut PwxReader, args: &Args) {
+    let (header, mut records) = read_all(p);
+    let idx = find_record(&records, args.recid());
+
+    if args.cmd_set {
+        let res = Totp::from_uri(&args.arg_uri).and_then(|totp| totp.set(&mut records[idx]));
+        if let Err(err) = res {
+            let _ = writeln!(stderr(), "{}", err);
+            exit(-1);
+        }
+        save_all(p, &header, &records);
+        return;
+    }
+
+    let now = Utc::now().timestamp();
+    match Totp::from_record(&records[idx]) {
+        Some(Ok(totp)) if args.output().is_structured() => {
+            let id = record::uuid(&records[idx]).map(|u| format_recid(args, &u)).unwrap_or_default();
+            let obj = serde_json::json!({"id": id, "code": totp.code(now), "remaining": totp.remaining(now)});
+            print_objects(args.output(), &["id", "code", "remaining"], &[obj], true);
+        }
+        Some(Ok(totp)) => println!("{}", totp.code(now)),
+        Some(Err(err)) => {
+            let _ = writeln!(stderr(), "{}", err);
+            exit(-1);
+        }
+        None => {
+            let _ = writeln!(stderr(), "Record has no two-factor key: {}", args.recid());
+            exit(-1);
+        }
+    }
+}
+
+fn cmd_get(p: &mut PwxReader, args: &Args) {
+    let (_, records) = read_all(p);
+    let idx = find_record(&records, args.recid());
+    let get_uuid = record::find(&records[idx], 0x01).cloned().expect("record uuid");
+
+    let mut found = None;
+    foreach_record(p, args, |recid, mut recdict, record| {
+        if get_uuid == recid {
+            // Card fields are masked unless asked for explicitly
+            for field in record {
+                match *field {
+                    Field::CreditCardNumber(ref val) |
+                    Field::CreditCardVerifValue(ref val) |
+                    Field::CreditCardPin(ref val) => {
+                        let name = field.name().unwrap_or_default().to_owned();
+                        recdict.insert(name, String::from_utf8_lossy(val.as_ref()).into_owned());
                     }
+                    _ => (),
                 }
             }
-            // unreachable
+            found = Some((recdict, record.to_vec()));
+            true
         } else {
             false
         }
     });
 
-    let _ = writeln!(stderr(), "Unknown record: {}", args.arg_recid);
-    exit(-1);
+    let (recdict, record) = match found {
+        Some(found) => found,
+        None => {
+            let _ = writeln!(stderr(), "Unknown record: {}", args.recid());
+            exit(-1);
+        }
+    };
+
+    let format = args.output();
+    if args.cmd_get {
+        let value = match recdict.get(&args.arg_fieldname) {
+            Some(value) => value,
+            None => {
+                let _ = writeln!(stderr(), "Record has no field: {}", args.arg_fieldname);
+                exit(-1);
+            }
+        };
+        if format.is_structured() {
+            // The field was asked for, secrets are included
+            let typed = record.iter()
+                .find(|f| f.name() == Some(args.arg_fieldname.as_str()))
+                .map(output::field_value)
+                .unwrap_or_else(|| serde_json::Value::from(value.as_str()));
+            let mut obj = serde_json::Map::new();
+            obj.insert("id".to_owned(), serde_json::Value::from(recdict["id"].as_str()));
+            obj.insert(args.arg_fieldname.clone(), typed);
+            let columns = ["id", args.arg_fieldname.as_str()];
+            print_objects(format, &columns, &[serde_json::Value::Object(obj)], true);
+        } else {
+            print_text(args, &format!("{}\n", value));
+        }
+    } else if format.is_structured() {
+        let obj = output::record_json(&record, args.flag_reveal);
+        print_objects(format, &output::columns(args.flag_reveal), &[obj], true);
+    } else if args.arg_fmt.is_empty() {
+        let _ = writeln!(stderr(), "getrec needs a format string, or --output");
+        exit(-1);
+    } else {
+        match apply_fmt(args, &args.arg_fmt, &recdict, &record) {
+            Ok(s) => {
+                print_text(args, &s);
+            }
+            Err(err) => {
+                let _ = writeln!(stderr(), "Error applying fmt string: {}", err);
+                exit(-1);
+            }
+        }
+    }
+
+    if args.flag_update_recent {
+        let uuid = Uuid::from_bytes(get_uuid.value().as_ref()).expect("Invalid record id");
+        if let Err(err) = update_recent(p, uuid) {
+            let _ = writeln!(stderr(), "Error saving database: {}", err);
+            exit(-1);
+        }
+    }
+    exit(0);
+}
+
+/// Print the database metadata
+fn cmd_info(p: &mut PwxReader, args: &Args) {
+    let info = p.info().unwrap();
+    let format = args.output();
+    if format.is_structured() {
+        let obj = serde_json::json!({
+            "uuid": info.uuid,
+            "mtime": info.mtime.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "user": info.user,
+            "host": info.host,
+            "name": info.dbname,
+            "description": info.description,
+        });
+        print_objects(format, &["uuid", "mtime", "user", "host", "name", "description"], &[obj], true);
+    } else {
+        let mtime = format_time(args, &info.mtime);
+        print_text(args, &format!("{} {} {}@{}\n", info.uuid, mtime, info.user, info.host));
+    }
 }
 
 fn main() {
@@ -378,12 +1995,46 @@ fn main() {
         exit(-1);
     }
 
+    let structured = args.cmd_list || args.cmd_recent || args.cmd_info || args.cmd_get || args.cmd_getrec ||
+                     args.cmd_expiring || args.cmd_cards || args.cmd_show || (args.cmd_otp && !args.cmd_set);
+    if args.output().is_structured() && !structured {
+        let _ = writeln!(stderr(), "--output {} is not supported by this command",
+                         args.flag_output);
+        exit(-1);
+    }
+
     if args.cmd_list {
         cmd_list(&mut p, &args)
+    } else if args.cmd_recent {
+        cmd_recent(&mut p, &args)
+    } else if args.cmd_expiring {
+        cmd_expiring(&mut p, &args)
+    } else if args.cmd_cards {
+        cmd_cards(&mut p, &args)
+    } else if args.cmd_protect || args.cmd_unprotect {
+        cmd_protect(&mut p, &args, args.cmd_protect)
+    } else if args.cmd_run {
+        cmd_run(&mut p, &args)
+    } else if args.cmd_autotype {
+        cmd_autotype(&mut p, &args)
+    } else if args.cmd_open {
+        cmd_open(&mut p, &args)
+    } else if args.cmd_add {
+        cmd_add(&mut p, &args)
+    } else if args.cmd_edit {
+        cmd_edit(&mut p, &args)
+    } else if args.cmd_show {
+        cmd_show(&mut p, &args)
+    } else if args.cmd_rm {
+        cmd_rm(&mut p, &args)
+    } else if args.cmd_mv {
+        cmd_mv(&mut p, &args)
+    } else if args.cmd_otp {
+        cmd_otp(&mut p, &args)
+    } else if args.cmd_filters {
+        cmd_filters(&mut p)
     } else if args.cmd_info {
-
-        let info = p.info().unwrap();
-        println!("{} {} {}@{}", info.uuid, info.mtime, info.user, info.host);
+        cmd_info(&mut p, &args)
     } else if args.cmd_get || args.cmd_getrec {
         cmd_get(&mut p, &args)
     }
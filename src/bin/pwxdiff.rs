@@ -3,10 +3,11 @@ extern crate pwx;
 extern crate docopt;
 extern crate rpassword;
 extern crate gpgagent;
-extern crate rust_base58;
+extern crate serde_json;
 
-use rust_base58::ToBase58;
-use pwx::{PwxReader, Field, Value};
+use pwx::{PwxReader, PwxInfo, Field, Value, record, recid, output};
+use serde_json::{json, Value as JsonValue};
+use pwx::diff::{self, Change, FieldChange};
 use docopt::Docopt;
 use std::io::{Write, stderr};
 use std::process::exit;
@@ -16,6 +17,11 @@ use std::path::{PathBuf, Path};
 // Get pkg version at compile time
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+/// Exit status if the databases differ, like diff(1)
+const EXIT_DIFFERENT: i32 = 1;
+/// Exit status on errors
+const EXIT_ERROR: i32 = 2;
+
 /// Convert path to absolute path
 pub fn abspath(p: &PathBuf) -> Result<PathBuf, std::io::Error> {
     if p.is_absolute() {
@@ -36,6 +42,9 @@ pub fn abspath(p: &PathBuf) -> Result<PathBuf, std::io::Error> {
 struct Args {
     arg_file1: String,
     arg_file2: String,
+    flag_output: String,
+    flag_quiet: bool,
+    flag_reveal: bool,
     flag_version: bool,
 }
 
@@ -45,379 +54,205 @@ fn open_db(path: &Path, description: &str) -> PwxReader {
         match PwxReader::open(&path, var.as_bytes()) {
             Err(err) => {
                 let _ = writeln!(stderr(), "Error opening {} with $PWX_PASSWORD: {}", path.to_string_lossy(), err);
-                exit(-1);
+                exit(EXIT_ERROR);
             }
             Ok(p) => p,
         }
     } else if let Ok(mut agent) = gpgagent::GpgAgent::from_standard_paths() {
         let _ = agent.setopt_ttyname();
         let cache_id = format!("pwx:{}", path.to_string_lossy());
-        let pass = agent.get_passphrase(&cache_id, "pwx", "Password", &description)
-            .map(|p| String::from_utf8(p).unwrap())
-            .expect("Unable to get password using gpg-agent");
-
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} using gpg-agent: {}", path.to_string_lossy(), err);
-                let _ = agent.clear_passphrase(&cache_id);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    } else {
-        // Get password from terminal
-        let pass = rpassword::prompt_password_stderr("Password: ")
-            .expect("Unable to read password from console");
-
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {}: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    };
-
-    if !p.authenticate().is_ok() {
-        exit(-1);
-    }
-    let mut p = if let Ok(var) = std::env::var("PWX_PASSWORD") {
-        match PwxReader::open(&path, var.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} with $PWX_PASSWORD: {}", path.to_string_lossy(), err);
-                exit(-1);
+        let pass = match agent.get_passphrase(&cache_id, "pwx", "Password", description) {
+            Ok(pass) => String::from_utf8_lossy(&pass).into_owned(),
+            Err(_) => {
+                let _ = writeln!(stderr(), "Unable to get password using gpg-agent");
+                exit(EXIT_ERROR);
             }
-            Ok(p) => p,
-        }
-    } else if let Ok(mut agent) = gpgagent::GpgAgent::from_standard_paths() {
-        let _ = agent.setopt_ttyname();
-        let cache_id = format!("pwx:{}", path.to_string_lossy());
-        let pass = agent.get_passphrase(&cache_id, "pwx", "Password", &description)
-            .map(|p| String::from_utf8(p).unwrap())
-            .expect("Unable to get password using gpg-agent");
+        };
 
         match PwxReader::open(&path, pass.as_bytes()) {
             Err(err) => {
                 let _ = writeln!(stderr(), "Error opening {} using gpg-agent: {}", path.to_string_lossy(), err);
                 let _ = agent.clear_passphrase(&cache_id);
-                exit(-1);
+                exit(EXIT_ERROR);
             }
             Ok(p) => p,
         }
     } else {
         // Get password from terminal
-        let pass = rpassword::prompt_password_stderr("Password: ")
-            .expect("Unable to read password from console");
-
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {}: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    };
-
-    if !p.authenticate().is_ok() {
-        exit(-1);
-    }
-    let mut p = if let Ok(var) = std::env::var("PWX_PASSWORD") {
-        match PwxReader::open(&path, var.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} with $PWX_PASSWORD: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    } else if let Ok(mut agent) = gpgagent::GpgAgent::from_standard_paths() {
-        let _ = agent.setopt_ttyname();
-        let cache_id = format!("pwx:{}", path.to_string_lossy());
-        let pass = agent.get_passphrase(&cache_id, "pwx", "Password", &description)
-            .map(|p| String::from_utf8(p).unwrap())
-            .expect("Unable to get password using gpg-agent");
-
-        match PwxReader::open(&path, pass.as_bytes()) {
+        let pass = match rpassword::prompt_password_stderr("Password: ") {
+            Ok(pass) => pass,
             Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} using gpg-agent: {}", path.to_string_lossy(), err);
-                let _ = agent.clear_passphrase(&cache_id);
-                exit(-1);
+                let _ = writeln!(stderr(), "Unable to read password from console: {}", err);
+                exit(EXIT_ERROR);
             }
-            Ok(p) => p,
-        }
-    } else {
-        // Get password from terminal
-        let pass = rpassword::prompt_password_stderr("Password: ")
-            .expect("Unable to read password from console");
+        };
 
         match PwxReader::open(&path, pass.as_bytes()) {
             Err(err) => {
                 let _ = writeln!(stderr(), "Error opening {}: {}", path.to_string_lossy(), err);
-                exit(-1);
+                exit(EXIT_ERROR);
             }
             Ok(p) => p,
         }
     };
 
     if !p.authenticate().is_ok() {
-        exit(-1);
+        exit(EXIT_ERROR);
     }
-    let mut p = if let Ok(var) = std::env::var("PWX_PASSWORD") {
-        match PwxReader::open(&path, var.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} with $PWX_PASSWORD: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    } else if let Ok(mut agent) = gpgagent::GpgAgent::from_standard_paths() {
-        let _ = agent.setopt_ttyname();
-        let cache_id = format!("pwx:{}", path.to_string_lossy());
-        let pass = agent.get_passphrase(&cache_id, "pwx", "Password", &description)
-            .map(|p| String::from_utf8(p).unwrap())
-            .expect("Unable to get password using gpg-agent");
-
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} using gpg-agent: {}", path.to_string_lossy(), err);
-                let _ = agent.clear_passphrase(&cache_id);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    } else {
-        // Get password from terminal
-        let pass = rpassword::prompt_password_stderr("Password: ")
-            .expect("Unable to read password from console");
+    p
+}
 
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {}: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
+/// Read the database header, exits on error
+fn read_info(p: &mut PwxReader, path: &Path) -> PwxInfo {
+    match p.info() {
+        Ok(info) => info,
+        Err(err) => {
+            let _ = writeln!(stderr(), "Error reading {}: {}", path.to_string_lossy(), err);
+            exit(EXIT_ERROR);
         }
-    };
-
-    if !p.authenticate().is_ok() {
-        exit(-1);
     }
-    let mut p = if let Ok(var) = std::env::var("PWX_PASSWORD") {
-        match PwxReader::open(&path, var.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} with $PWX_PASSWORD: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    } else if let Ok(mut agent) = gpgagent::GpgAgent::from_standard_paths() {
-        let _ = agent.setopt_ttyname();
-        let cache_id = format!("pwx:{}", path.to_string_lossy());
-        let pass = agent.get_passphrase(&cache_id, "pwx", "Password", &description)
-            .map(|p| String::from_utf8(p).unwrap())
-            .expect("Unable to get password using gpg-agent");
+}
 
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} using gpg-agent: {}", path.to_string_lossy(), err);
-                let _ = agent.clear_passphrase(&cache_id);
-                exit(-1);
-            }
-            Ok(p) => p,
+/// Read all records, exits on error
+fn read_records(p: &mut PwxReader, path: &Path) -> Vec<Vec<Field>> {
+    match p.records().and_then(|records| records.collect()) {
+        Ok(records) => records,
+        Err(err) => {
+            let _ = writeln!(stderr(), "Error reading {}: {}", path.to_string_lossy(), err);
+            exit(EXIT_ERROR);
         }
-    } else {
-        // Get password from terminal
-        let pass = rpassword::prompt_password_stderr("Password: ")
-            .expect("Unable to read password from console");
-
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {}: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    };
-
-    if !p.authenticate().is_ok() {
-        exit(-1);
     }
-    let mut p = if let Ok(var) = std::env::var("PWX_PASSWORD") {
-        match PwxReader::open(&path, var.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} with $PWX_PASSWORD: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    } else if let Ok(mut agent) = gpgagent::GpgAgent::from_standard_paths() {
-        let _ = agent.setopt_ttyname();
-        let cache_id = format!("pwx:{}", path.to_string_lossy());
-        let pass = agent.get_passphrase(&cache_id, "pwx", "Password", &description)
-            .map(|p| String::from_utf8(p).unwrap())
-            .expect("Unable to get password using gpg-agent");
-
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} using gpg-agent: {}", path.to_string_lossy(), err);
-                let _ = agent.clear_passphrase(&cache_id);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    } else {
-        // Get password from terminal
-        let pass = rpassword::prompt_password_stderr("Password: ")
-            .expect("Unable to read password from console");
+}
 
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {}: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    };
+/// The record id, title and username
+fn describe(rec: &[Field]) -> String {
+    let id = record::uuid(rec).map(|uuid| recid::base58(&uuid)).unwrap_or_else(|| "(no uuid)".to_owned());
+    let text = |typ| record::find(rec, typ).map(|f| format!("{}", f)).unwrap_or_default();
+    format!("{} {} [{}]", id, text(0x03), text(0x04))
+}
 
-    if !p.authenticate().is_ok() {
-        exit(-1);
-    }
-    let mut p = if let Ok(var) = std::env::var("PWX_PASSWORD") {
-        match PwxReader::open(&path, var.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} with $PWX_PASSWORD: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    } else if let Ok(mut agent) = gpgagent::GpgAgent::from_standard_paths() {
-        let _ = agent.setopt_ttyname();
-        let cache_id = format!("pwx:{}", path.to_string_lossy());
-        let pass = agent.get_passphrase(&cache_id, "pwx", "Password", &description)
-            .map(|p| String::from_utf8(p).unwrap())
-            .expect("Unable to get password using gpg-agent");
+/// Secret fields, the password history (0x0f) has the old passwords
+fn is_secret(typ: u8) -> bool {
+    typ == 0x0f || output::SECRET_FIELDS.contains(&typ)
+}
 
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} using gpg-agent: {}", path.to_string_lossy(), err);
-                let _ = agent.clear_passphrase(&cache_id);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
+/// A field value on one line, or `(none)`. Unknown fields are shown as
+/// hex, card secrets as is.
+fn show_value(field: Option<&Field>) -> String {
+    let field = match field {
+        Some(field) => field,
+        None => return "(none)".to_owned(),
+    };
+    let val = field.value().as_ref();
+    let text = if field.name().is_none() {
+        val.iter().map(|b| format!("{:02x}", b)).collect()
+    } else if is_secret(field.typ()) {
+        String::from_utf8_lossy(val).into_owned()
     } else {
-        // Get password from terminal
-        let pass = rpassword::prompt_password_stderr("Password: ")
-            .expect("Unable to read password from console");
-
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {}: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
+        format!("{}", field)
     };
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
 
-    if !p.authenticate().is_ok() {
-        exit(-1);
-    }
-    let mut p = if let Ok(var) = std::env::var("PWX_PASSWORD") {
-        match PwxReader::open(&path, var.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} with $PWX_PASSWORD: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    } else if let Ok(mut agent) = gpgagent::GpgAgent::from_standard_paths() {
-        let _ = agent.setopt_ttyname();
-        let cache_id = format!("pwx:{}", path.to_string_lossy());
-        let pass = agent.get_passphrase(&cache_id, "pwx", "Password", &description)
-            .map(|p| String::from_utf8(p).unwrap())
-            .expect("Unable to get password using gpg-agent");
+/// The name of a field type, or its hex id
+fn field_name(typ: u8) -> String {
+    Field::from(typ, Value::from(vec![]))
+        .name()
+        .map(|name| name.to_owned())
+        .unwrap_or_else(|| format!("0x{:02x}", typ))
+}
 
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} using gpg-agent: {}", path.to_string_lossy(), err);
-                let _ = agent.clear_passphrase(&cache_id);
-                exit(-1);
-            }
-            Ok(p) => p,
+/// Describe a field change. Secrets are only shown with --reveal, a
+/// password change shows the password modification times instead.
+fn show_change(change: &FieldChange, rec1: &[Field], rec2: &[Field], reveal: bool) -> String {
+    let name = field_name(change.typ);
+    if !reveal && is_secret(change.typ) {
+        let mut s = format!("{} changed", name);
+        if change.typ == 0x06 {
+            s.push_str(&format!(", ptime {} -> {}", show_value(record::find(rec1, 0x08)),
+                                show_value(record::find(rec2, 0x08))));
         }
+        s
     } else {
-        // Get password from terminal
-        let pass = rpassword::prompt_password_stderr("Password: ")
-            .expect("Unable to read password from console");
-
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {}: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    };
-
-    if !p.authenticate().is_ok() {
-        exit(-1);
+        format!("{}: {} -> {}", name, show_value(change.old), show_value(change.new))
     }
-    let mut p = if let Ok(var) = std::env::var("PWX_PASSWORD") {
-        match PwxReader::open(&path, var.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} with $PWX_PASSWORD: {}", path.to_string_lossy(), err);
-                exit(-1);
-            }
-            Ok(p) => p,
-        }
-    } else if let Ok(mut agent) = gpgagent::GpgAgent::from_standard_paths() {
-        let _ = agent.setopt_ttyname();
-        let cache_id = format!("pwx:{}", path.to_string_lossy());
-        let pass = agent.get_passphrase(&cache_id, "pwx", "Password", &description)
-            .map(|p| String::from_utf8(p).unwrap())
-            .expect("Unable to get password using gpg-agent");
+}
 
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {} using gpg-agent: {}", path.to_string_lossy(), err);
-                let _ = agent.clear_passphrase(&cache_id);
-                exit(-1);
-            }
-            Ok(p) => p,
+/// Print the changes as text
+fn print_text(header: &[(&str, String, String)], changes: &[Change], reveal: bool) {
+    if !header.is_empty() {
+        println!("Changed header");
+        for (name, old, new) in header {
+            println!("  {}: {} -> {}", name, old, new);
         }
-    } else {
-        // Get password from terminal
-        let pass = rpassword::prompt_password_stderr("Password: ")
-            .expect("Unable to read password from console");
-
-        match PwxReader::open(&path, pass.as_bytes()) {
-            Err(err) => {
-                let _ = writeln!(stderr(), "Error opening {}: {}", path.to_string_lossy(), err);
-                exit(-1);
+    }
+    for change in changes {
+        match *change {
+            Change::Removed(rec) => println!("Removed {}", describe(rec)),
+            Change::Added(rec) => println!("Added {}", describe(rec)),
+            Change::Changed(rec1, rec2) => {
+                println!("Changed {}", describe(rec2));
+                for change in diff::diff_fields(rec1, rec2) {
+                    println!("  {}", show_change(&change, rec1, rec2, reveal));
+                }
             }
-            Ok(p) => p,
         }
-    };
-
-    if !p.authenticate().is_ok() {
-        exit(-1);
     }
-    p
 }
 
-fn find_uuid(fields: &[Field]) -> Option<Value> {
-    for f in fields {
-        if let Field::Uuid(v) = f {
-            return Some(v.clone());
+/// Print the changes as a JSON object with the `header` changes, and the
+/// `added`, `removed` and `changed` records. Added and removed records
+/// are complete, see `pwx list --output json`, changed records have the
+/// `id`, `title` and the changed `fields` with their `old` and `new`
+/// values. Secrets have no values unless --reveal.
+fn print_json(header: &[(&str, String, String)], changes: &[Change], reveal: bool) {
+    let header: Vec<_> = header.iter()
+        .map(|(name, old, new)| json!({"field": name, "old": old, "new": new}))
+        .collect();
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut changed = Vec::new();
+    for change in changes {
+        match *change {
+            Change::Added(rec) => added.push(output::record_json(rec, reveal)),
+            Change::Removed(rec) => removed.push(output::record_json(rec, reveal)),
+            Change::Changed(rec1, rec2) => {
+                let fields: Vec<_> = diff::diff_fields(rec1, rec2)
+                    .iter()
+                    .map(|change| {
+                        let name = field_name(change.typ);
+                        if !reveal && is_secret(change.typ) {
+                            json!({"field": name})
+                        } else {
+                            let value = |f: Option<&Field>| f.map(output::field_value).unwrap_or(JsonValue::Null);
+                            json!({"field": name, "old": value(change.old), "new": value(change.new)})
+                        }
+                    })
+                    .collect();
+                let text = |typ| record::find(rec2, typ).map(|f| format!("{}", f));
+                changed.push(json!({
+                    "id": record::uuid(rec2).map(|uuid| recid::base58(&uuid)),
+                    "title": text(0x03),
+                    "fields": fields,
+                }));
+            }
         }
     }
-    return None;
+    let obj = json!({"header": header, "added": added, "removed": removed, "changed": changed});
+    println!("{}", serde_json::to_string_pretty(&obj).expect("json"));
 }
 
 fn main() {
     let args: Args = Docopt::new(include_str!(concat!(env!("CARGO_MANIFEST_DIR"),
                                                       "/doc/pwxdiff.docopt")))
                          .and_then(|d| d.deserialize())
-                         .unwrap_or_else(|e| e.exit());
+                         .unwrap_or_else(|e| {
+                             if e.fatal() {
+                                 let _ = writeln!(stderr(), "{}", e);
+                                 exit(EXIT_ERROR);
+                             }
+                             e.exit()
+                         });
 
     if args.flag_version {
         println!("pwxdiff {}", VERSION);
@@ -432,7 +267,7 @@ fn main() {
 
     if !path1.exists() {
         let _ = writeln!(stderr(), "File does not exist: {}", path1.to_string_lossy());
-        exit(-1);
+        exit(EXIT_ERROR);
     }
 
     let mut path2 = PathBuf::from(&args.arg_file2);
@@ -443,7 +278,7 @@ fn main() {
 
     if !path2.exists() {
         let _ = writeln!(stderr(), "File does not exist: {}", path2.to_string_lossy());
-        exit(-1);
+        exit(EXIT_ERROR);
     }
 
     let description = format!("Opening {}", path1.to_string_lossy());
@@ -452,50 +287,29 @@ fn main() {
     let description = format!("Opening {}", path2.to_string_lossy());
     let mut p2 = open_db(&path2, &description);
 
-    let p1info = p1.info().unwrap();
-    let p2info = p2.info().unwrap();
-    if p1info != p2info {
-        println!("file1: {:#?}", p1info);
-        println!("file2: {:#?}", p2info);
-    }
-    for rec1 in p1.records().expect("Error reading from <file1>") {
-        let rec1 = rec1.unwrap();
-
-        match find_uuid(&rec1) {
-            None => {
-                let _ = writeln!(stderr(), "Found record with no UUID in file1");
-                continue;
-            }
-            Some(ref uuid1) => {
-                let mut found = false;
-                for rec2 in p2.records().expect("Error reading from <file2>") {
-                    let rec2 = rec2.unwrap();
-                    match find_uuid(&rec2) {
-                        None => {
-                            let _ = writeln!(stderr(), "Found record with no UUID in <file2>");
-                            continue;
-                        }
-                        Some(ref uuid2) if uuid1 == uuid2 => {
-                            found = true;
-                            if rec2 != rec1 {
-                                println!("Records are different {}", uuid1.as_ref().to_base58());
-                                for f in rec1 {
-                                    println!("- {}", f)
-                                }
-                                for f in rec2 {
-                                    println!("+ {}", f)
-                                }
-                            }
-                            break;
-                        }
-                        Some(_) => (),
-                    }
-                }
-
-                if !found {
-                    println!("Record not found {}", uuid1.as_ref().to_base58())
-                }
-            }
+    let format = match output::Format::from_name(&args.flag_output) {
+        Some(format @ output::Format::Text) | Some(format @ output::Format::Json) => format,
+        _ => {
+            let _ = writeln!(stderr(), "Invalid output format: {}", args.flag_output);
+            exit(EXIT_ERROR);
         }
+    };
+
+    let info1 = read_info(&mut p1, &path1);
+    let info2 = read_info(&mut p2, &path2);
+    let header = diff::diff_info(&info1, &info2);
+    let records1 = read_records(&mut p1, &path1);
+    let records2 = read_records(&mut p2, &path2);
+    let changes = diff::diff(&records1, &records2);
+
+    if args.flag_quiet {
+        // Only the exit status
+    } else if format == output::Format::Json {
+        print_json(&header, &changes, args.flag_reveal);
+    } else {
+        print_text(&header, &changes, args.flag_reveal);
+    }
+    if !header.is_empty() || !changes.is_empty() {
+        exit(EXIT_DIFFERENT);
     }
 }
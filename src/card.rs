@@ -0,0 +1,148 @@
+//! Credit card fields (0x1c-0x1f)
+
+use super::db::Field;
+use super::record;
+use super::chrono::{Datelike, NaiveDate};
+
+/// The credit card fields of a record
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreditCard {
+    pub number: String,
+    pub expiration: Option<String>,
+    pub verif_value: Option<String>,
+    pub pin: Option<String>,
+}
+
+fn text(rec: &[Field], typ: u8) -> Option<String> {
+    record::find(rec, typ).map(|f| String::from_utf8_lossy(f.value().as_ref()).into_owned())
+}
+
+impl CreditCard {
+    /// Read the card from a record, None if the record has no card number
+    pub fn from_record(rec: &[Field]) -> Option<CreditCard> {
+        Some(CreditCard {
+            number: text(rec, 0x1c)?,
+            expiration: text(rec, 0x1d),
+            verif_value: text(rec, 0x1e),
+            pin: text(rec, 0x1f),
+        })
+    }
+
+    /// The card number without spaces or dashes
+    pub fn digits(&self) -> String {
+        self.number.chars().filter(|c| *c != ' ' && *c != '-').collect()
+    }
+
+    /// Card numbers have 12-19 digits and a valid Luhn checksum
+    pub fn is_valid(&self) -> bool {
+        let digits = self.digits();
+        (12..=19).contains(&digits.len()) && luhn(&digits)
+    }
+
+    /// The card number with all but the last 4 digits masked
+    pub fn masked(&self) -> String {
+        mask(&self.number)
+    }
+
+    /// The last day the card is valid, None if there is no expiration
+    /// date or it can't be parsed
+    pub fn expiry(&self) -> Option<NaiveDate> {
+        self.expiration.as_ref().and_then(|s| parse_expiry(s))
+    }
+}
+
+/// Check the Luhn checksum of a string of digits
+pub fn luhn(digits: &str) -> bool {
+    let mut sum = 0;
+    for (i, c) in digits.chars().rev().enumerate() {
+        let mut d = match c.to_digit(10) {
+            Some(d) => d,
+            None => return false,
+        };
+        if i % 2 == 1 {
+            d *= 2;
+            if d > 9 {
+                d -= 9;
+            }
+        }
+        sum += d;
+    }
+    !digits.is_empty() && sum % 10 == 0
+}
+
+/// Mask a card number, only the last 4 digits are shown
+pub fn mask(number: &str) -> String {
+    let digits: Vec<char> = number.chars().filter(|c| c.is_ascii_digit()).collect();
+    let last: String = digits[digits.len().saturating_sub(4)..].iter().collect();
+    format!("**** {}", last)
+}
+
+/// Parse a card expiration date, `MM/YY`, `MM/YYYY`, `MM-YY`, `MMYY` or
+/// `YYYY-MM`. Returns the last day of the month.
+pub fn parse_expiry(s: &str) -> Option<NaiveDate> {
+    let s = s.trim();
+    let parts: Vec<&str> = s.split(['/', '-']).map(|p| p.trim()).collect();
+    let (month, year) = match parts.as_slice() {
+        [y, m] if y.len() == 4 => (*m, *y),
+        [m, y] => (*m, *y),
+        [my] if my.len() == 4 => (&my[..2], &my[2..]),
+        _ => return None,
+    };
+    if !month.chars().chain(year.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let month: u32 = month.parse().ok()?;
+    let year: i32 = match year.len() {
+        2 => 2000 + year.parse::<i32>().ok()?,
+        4 => year.parse().ok()?,
+        _ => return None,
+    };
+
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let next = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)?
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)?
+    };
+    next.pred_opt().filter(|last| last.month() == first.month())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_luhn() {
+        assert!(luhn("4111111111111111"));
+        assert!(luhn("79927398713"));
+        assert!(!luhn("4111111111111112"));
+        assert!(!luhn("4111a11111111111"));
+        assert!(!luhn(""));
+
+        let card = CreditCard {
+            number: "4111 1111-1111 1111".to_owned(),
+            expiration: Some("02/24".to_owned()),
+            verif_value: None,
+            pin: None,
+        };
+        assert!(card.is_valid());
+        assert_eq!(card.masked(), "**** 1111");
+        assert_eq!(card.expiry(), NaiveDate::from_ymd_opt(2024, 2, 29));
+        assert_eq!(mask("12"), "**** 12");
+    }
+
+    #[test]
+    fn test_parse_expiry() {
+        let date = NaiveDate::from_ymd_opt(2027, 12, 31);
+        assert_eq!(parse_expiry("12/27"), date);
+        assert_eq!(parse_expiry("12/2027"), date);
+        assert_eq!(parse_expiry("12-27"), date);
+        assert_eq!(parse_expiry("1227"), date);
+        assert_eq!(parse_expiry("2027-12"), date);
+        assert_eq!(parse_expiry(" 12 / 27 "), date);
+        assert_eq!(parse_expiry("13/27"), None);
+        assert_eq!(parse_expiry("ab/cd"), None);
+        assert_eq!(parse_expiry("12/271"), None);
+        assert_eq!(parse_expiry(""), None);
+    }
+}
@@ -1,10 +1,11 @@
 
 use std::fmt;
 use super::util;
+use super::card;
+use super::action::Action;
 use super::uuid::Uuid;
 use super::secstr::SecStr;
 use std::borrow::Borrow;
-use super::chrono::naive::NaiveDateTime;
 
 #[derive(PartialEq, Clone)]
 pub enum Field {
@@ -17,9 +18,24 @@ pub enum Field {
     CreationTime(Value),
     PasswordModificationTime(Value),
     LastAccessTime(Value),
+    PasswordExpiryTime(Value),
+    LastModificationTime(Value),
     Url(Value),
+    PasswordExpiryInterval(Value),
     Command(Value),
+    DoubleClickAction(Value),
     Email(Value),
+    Protected(Value),
+    ShiftDoubleClickAction(Value),
+    TwoFactorKey(Value),
+    CreditCardNumber(Value),
+    CreditCardExpiration(Value),
+    CreditCardVerifValue(Value),
+    CreditCardPin(Value),
+    TotpConfig(Value),
+    TotpLength(Value),
+    TotpTimeStep(Value),
+    TotpStartTime(Value),
     Unknown(u8, Value),
 }
 
@@ -35,15 +51,103 @@ impl Field {
             0x07 => Field::CreationTime(val),
             0x08 => Field::PasswordModificationTime(val),
             0x09 => Field::LastAccessTime(val),
+            0x0a => Field::PasswordExpiryTime(val),
+            0x0c => Field::LastModificationTime(val),
             0x0d => Field::Url(val),
+            0x11 => Field::PasswordExpiryInterval(val),
             0x12 => Field::Command(val),
+            0x13 => Field::DoubleClickAction(val),
             0x14 => Field::Email(val),
+            0x15 => Field::Protected(val),
+            0x17 => Field::ShiftDoubleClickAction(val),
+            0x1b => Field::TwoFactorKey(val),
+            0x1c => Field::CreditCardNumber(val),
+            0x1d => Field::CreditCardExpiration(val),
+            0x1e => Field::CreditCardVerifValue(val),
+            0x1f => Field::CreditCardPin(val),
+            0x21 => Field::TotpConfig(val),
+            0x22 => Field::TotpLength(val),
+            0x23 => Field::TotpTimeStep(val),
+            0x24 => Field::TotpStartTime(val),
             _ => Field::Unknown(typ, val),
         }
     }
 
+    /// Return the field type identifier
+    pub fn typ(&self) -> u8 {
+        match *self {
+            Field::Uuid(_) => 0x01,
+            Field::Group(_) => 0x02,
+            Field::Title(_) => 0x03,
+            Field::Username(_) => 0x04,
+            Field::Notes(_) => 0x05,
+            Field::Password(_) => 0x06,
+            Field::CreationTime(_) => 0x07,
+            Field::PasswordModificationTime(_) => 0x08,
+            Field::LastAccessTime(_) => 0x09,
+            Field::PasswordExpiryTime(_) => 0x0a,
+            Field::LastModificationTime(_) => 0x0c,
+            Field::Url(_) => 0x0d,
+            Field::PasswordExpiryInterval(_) => 0x11,
+            Field::Command(_) => 0x12,
+            Field::DoubleClickAction(_) => 0x13,
+            Field::Email(_) => 0x14,
+            Field::Protected(_) => 0x15,
+            Field::ShiftDoubleClickAction(_) => 0x17,
+            Field::TwoFactorKey(_) => 0x1b,
+            Field::CreditCardNumber(_) => 0x1c,
+            Field::CreditCardExpiration(_) => 0x1d,
+            Field::CreditCardVerifValue(_) => 0x1e,
+            Field::CreditCardPin(_) => 0x1f,
+            Field::TotpConfig(_) => 0x21,
+            Field::TotpLength(_) => 0x22,
+            Field::TotpTimeStep(_) => 0x23,
+            Field::TotpStartTime(_) => 0x24,
+            Field::Unknown(typ, _) => typ,
+        }
+    }
+
+    /// Return the raw field value
+    pub fn value(&self) -> &Value {
+        match *self {
+            Field::Uuid(ref v) |
+            Field::Group(ref v) |
+            Field::Title(ref v) |
+            Field::Username(ref v) |
+            Field::Notes(ref v) |
+            Field::Password(ref v) |
+            Field::CreationTime(ref v) |
+            Field::PasswordModificationTime(ref v) |
+            Field::LastAccessTime(ref v) |
+            Field::PasswordExpiryTime(ref v) |
+            Field::LastModificationTime(ref v) |
+            Field::Url(ref v) |
+            Field::PasswordExpiryInterval(ref v) |
+            Field::Command(ref v) |
+            Field::DoubleClickAction(ref v) |
+            Field::Email(ref v) |
+            Field::Protected(ref v) |
+            Field::ShiftDoubleClickAction(ref v) |
+            Field::TwoFactorKey(ref v) |
+            Field::CreditCardNumber(ref v) |
+            Field::CreditCardExpiration(ref v) |
+            Field::CreditCardVerifValue(ref v) |
+            Field::CreditCardPin(ref v) |
+            Field::TotpConfig(ref v) |
+            Field::TotpLength(ref v) |
+            Field::TotpTimeStep(ref v) |
+            Field::TotpStartTime(ref v) |
+            Field::Unknown(_, ref v) => v,
+        }
+    }
+
+    /// Return the field type for a field name, see `name()`
+    pub fn typ_from_name(name: &str) -> Option<u8> {
+        (0..=0xff).find(|typ| Field::from(*typ, Value::from(vec![])).name() == Some(name))
+    }
+
     /// Return human readable field name
-    pub fn name(&self) -> Option<&str> {
+    pub fn name(&self) -> Option<&'static str> {
         match *self {
             Field::Uuid(_) => Some("uuid"),
             Field::Group(_) => Some("group"),
@@ -57,9 +161,63 @@ impl Field {
             Field::Url(_) => Some("url"),
             Field::Email(_) => Some("email"),
             Field::Command(_) => Some("command"),
+            Field::PasswordExpiryTime(_) => Some("expiry"),
+            Field::PasswordExpiryInterval(_) => Some("expiry_interval"),
+            Field::Protected(_) => Some("protected"),
+            Field::TwoFactorKey(_) => Some("two_factor_key"),
+            Field::TotpConfig(_) => Some("totp_config"),
+            Field::TotpLength(_) => Some("totp_length"),
+            Field::TotpTimeStep(_) => Some("totp_time_step"),
+            Field::TotpStartTime(_) => Some("totp_start_time"),
+            Field::CreditCardNumber(_) => Some("cc_number"),
+            Field::CreditCardExpiration(_) => Some("cc_expiry"),
+            Field::CreditCardVerifValue(_) => Some("cc_cvv"),
+            Field::CreditCardPin(_) => Some("cc_pin"),
+            Field::DoubleClickAction(_) => Some("dca"),
+            Field::ShiftDoubleClickAction(_) => Some("shift_dca"),
+            Field::LastModificationTime(_) => Some("mtime"),
             _ => None,
         }
     }
+
+    /// Return a label for display, e.g. "Password modification time"
+    pub fn label(&self) -> Option<&str> {
+        match *self {
+            Field::Uuid(_) => Some("UUID"),
+            Field::Group(_) => Some("Group"),
+            Field::Title(_) => Some("Title"),
+            Field::Username(_) => Some("Username"),
+            Field::Notes(_) => Some("Notes"),
+            Field::Password(_) => Some("Password"),
+            Field::CreationTime(_) => Some("Created"),
+            Field::PasswordModificationTime(_) => Some("Password changed"),
+            Field::LastAccessTime(_) => Some("Last accessed"),
+            Field::PasswordExpiryTime(_) => Some("Password expires"),
+            Field::LastModificationTime(_) => Some("Last modified"),
+            Field::Url(_) => Some("URL"),
+            Field::PasswordExpiryInterval(_) => Some("Expiry interval"),
+            Field::Command(_) => Some("Run command"),
+            Field::DoubleClickAction(_) => Some("Double-click action"),
+            Field::Email(_) => Some("Email"),
+            Field::Protected(_) => Some("Protected"),
+            Field::ShiftDoubleClickAction(_) => Some("Shift-double-click action"),
+            Field::TwoFactorKey(_) => Some("Two-factor key"),
+            Field::CreditCardNumber(_) => Some("Card number"),
+            Field::CreditCardExpiration(_) => Some("Card expiration"),
+            Field::CreditCardVerifValue(_) => Some("Card verification value"),
+            Field::CreditCardPin(_) => Some("Card PIN"),
+            Field::TotpConfig(_) => Some("TOTP algorithm"),
+            Field::TotpLength(_) => Some("TOTP digits"),
+            Field::TotpTimeStep(_) => Some("TOTP time step"),
+            Field::TotpStartTime(_) => Some("TOTP start time"),
+            Field::Unknown(..) => None,
+        }
+    }
+}
+
+/// Times are shown in UTC, invalid values as the epoch
+fn write_time(fmt: &mut fmt::Formatter, val: &[u8]) -> fmt::Result {
+    write!(fmt, "{}", util::from_time_t(val).unwrap_or_default())
 }
 
 impl fmt::Display for Field {
@@ -93,19 +251,13 @@ impl fmt::Display for Field {
                 fmt.write_str(&s)
             }
             Field::CreationTime(ref val) => {
-                let ts = util::from_time_t(val.as_ref())
-                             .unwrap_or(NaiveDateTime::from_timestamp(0, 0));
-                write!(fmt, "{}", ts)
+                write_time(fmt, val.as_ref())
             }
             Field::PasswordModificationTime(ref val) => {
-                let ts = util::from_time_t(val.as_ref())
-                             .unwrap_or(NaiveDateTime::from_timestamp(0, 0));
-                write!(fmt, "{}", ts)
+                write_time(fmt, val.as_ref())
             }
             Field::LastAccessTime(ref val) => {
-                let ts = util::from_time_t(val.as_ref())
-                             .unwrap_or(NaiveDateTime::from_timestamp(0, 0));
-                write!(fmt, "{}", ts)
+                write_time(fmt, val.as_ref())
             }
             Field::Url(ref v) => {
                 let s = String::from_utf8_lossy(v.as_ref());
@@ -119,6 +271,50 @@ impl fmt::Display for Field {
                 let s = String::from_utf8_lossy(v.as_ref());
                 fmt.write_str(&s)
             }
+            Field::PasswordExpiryTime(ref val) => {
+                write_time(fmt, val.as_ref())
+            }
+            Field::PasswordExpiryInterval(ref val) => {
+                write!(fmt, "{}", util::read_uint(val.as_ref()).unwrap_or(0))
+            }
+            Field::DoubleClickAction(ref v) |
+            Field::ShiftDoubleClickAction(ref v) => {
+                fmt.write_str(Action::from_value(v.as_ref()).name())
+            }
+            Field::Protected(ref v) => {
+                let protected = v.as_ref().iter().any(|b| *b != 0);
+                fmt.write_str(if protected { "1" } else { "0" })
+            }
+            Field::TwoFactorKey(ref v) => {
+                let s = String::from_utf8_lossy(v.as_ref());
+                fmt.write_str(&s)
+            }
+            Field::TotpConfig(ref val) => {
+                write!(fmt, "{}", util::read_uint(val.as_ref()).unwrap_or(0))
+            }
+            Field::TotpLength(ref val) => {
+                write!(fmt, "{}", util::read_uint(val.as_ref()).unwrap_or(0))
+            }
+            Field::TotpTimeStep(ref val) => {
+                write!(fmt, "{}", util::read_uint(val.as_ref()).unwrap_or(0))
+            }
+            Field::TotpStartTime(ref val) => {
+                write_time(fmt, val.as_ref())
+            }
+            // Card secrets are masked, use value() to get the real value
+            Field::CreditCardNumber(ref v) => {
+                let s = String::from_utf8_lossy(v.as_ref());
+                fmt.write_str(&card::mask(&s))
+            }
+            Field::CreditCardExpiration(ref v) => {
+                let s = String::from_utf8_lossy(v.as_ref());
+                fmt.write_str(&s)
+            }
+            Field::CreditCardVerifValue(_) |
+            Field::CreditCardPin(_) => fmt.write_str("****"),
+            Field::LastModificationTime(ref val) => {
+                write_time(fmt, val.as_ref())
+            }
             Field::Unknown(typ, _) => write!(fmt, "Unknown Field({})", typ),
         }
     }
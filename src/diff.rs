@@ -0,0 +1,239 @@
+//! Compare two databases, e.g. for `pwxdiff`
+//!
+//! Records are matched by UUID. Records without a UUID, or with a UUID
+//! already seen in the same database, are matched by their content, i.e.
+//! they are either unchanged or reported as removed and added. Changed
+//! records are compared field by field, absent and empty fields are the
+//! same, and the order of the fields does not matter.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use super::PwxInfo;
+use super::db::Field;
+use super::record;
+use super::recid;
+use super::uuid::Uuid;
+use super::chrono::{DateTime, SecondsFormat, Utc};
+
+/// A difference between the old and the new records
+#[derive(PartialEq)]
+pub enum Change<'a> {
+    /// Only in the new records
+    Added(&'a [Field]),
+    /// Only in the old records
+    Removed(&'a [Field]),
+    /// The old and new version of a record
+    Changed(&'a [Field], &'a [Field]),
+}
+
+/// Split records into those with a unique UUID, and the rest
+fn by_uuid(records: &[Vec<Field>]) -> (HashMap<Uuid, usize>, Vec<usize>) {
+    let mut uuids = HashMap::with_capacity(records.len());
+    let mut other = Vec::new();
+    for (idx, rec) in records.iter().enumerate() {
+        match record::uuid(rec) {
+            Some(uuid) if !uuids.contains_key(&uuid) => {
+                uuids.insert(uuid, idx);
+            }
+            _ => other.push(idx),
+        }
+    }
+    (uuids, other)
+}
+
+/// Compare two lists of records. Removed and changed records are in the
+/// order of `old`, followed by the added records in the order of `new`.
+pub fn diff<'a>(old: &'a [Vec<Field>], new: &'a [Vec<Field>]) -> Vec<Change<'a>> {
+    let (old_uuids, _) = by_uuid(old);
+    let (new_uuids, mut new_other) = by_uuid(new);
+    let mut matched = vec![false; new.len()];
+    let mut changes = Vec::new();
+
+    for (idx, rec) in old.iter().enumerate() {
+        let uuid = record::uuid(rec).filter(|uuid| old_uuids.get(uuid) == Some(&idx));
+        let other = match uuid.and_then(|uuid| new_uuids.get(&uuid)) {
+            Some(&other) => Some(other),
+            None if uuid.is_none() => {
+                new_other.iter()
+                    .position(|&other| diff_fields(rec, &new[other]).is_empty())
+                    .map(|pos| new_other.remove(pos))
+            }
+            None => None,
+        };
+        match other {
+            Some(other) => {
+                matched[other] = true;
+                if !diff_fields(rec, &new[other]).is_empty() {
+                    changes.push(Change::Changed(rec, &new[other]));
+                }
+            }
+            None => changes.push(Change::Removed(rec)),
+        }
+    }
+
+    for (idx, rec) in new.iter().enumerate() {
+        if !matched[idx] {
+            changes.push(Change::Added(rec));
+        }
+    }
+    changes
+}
+
+/// A field that differs between two versions of a record, `None` if the
+/// field is absent
+pub struct FieldChange<'a> {
+    pub typ: u8,
+    pub old: Option<&'a Field>,
+    pub new: Option<&'a Field>,
+}
+
+/// The non-empty fields of a type, sorted by value
+fn values(record: &[Field], typ: u8) -> Vec<&Field> {
+    let mut fields: Vec<_> = record.iter()
+        .filter(|f| f.typ() == typ && !f.value().as_ref().is_empty())
+        .collect();
+    fields.sort_by(|a, b| a.value().as_ref().cmp(b.value().as_ref()));
+    fields
+}
+
+/// Compare the fields of two versions of a record, in type order.
+/// Repeated fields of a type are compared as a set of values, the values
+/// only in `old` are paired with those only in `new`.
+pub fn diff_fields<'a>(old: &'a [Field], new: &'a [Field]) -> Vec<FieldChange<'a>> {
+    let mut types: Vec<u8> = old.iter().chain(new).map(|f| f.typ()).collect();
+    types.sort_unstable();
+    types.dedup();
+
+    let mut changes = Vec::new();
+    for typ in types {
+        let (old, new) = (values(old, typ), values(new, typ));
+        let (mut removed, mut added) = (Vec::new(), Vec::new());
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() || j < new.len() {
+            let order = match (old.get(i), new.get(j)) {
+                (Some(a), Some(b)) => a.value().as_ref().cmp(b.value().as_ref()),
+                (Some(_), None) => Ordering::Less,
+                _ => Ordering::Greater,
+            };
+            match order {
+                Ordering::Less => {
+                    removed.push(old[i]);
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    added.push(new[j]);
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        for k in 0..removed.len().max(added.len()) {
+            changes.push(FieldChange { typ, old: removed.get(k).cloned(), new: added.get(k).cloned() });
+        }
+    }
+    changes
+}
+
+/// Compare database headers, returns the name, old and new value of the
+/// fields that differ. Times are RFC 3339 (UTC).
+pub fn diff_info(old: &PwxInfo, new: &PwxInfo) -> Vec<(&'static str, String, String)> {
+    let rfc3339 = |ts: &DateTime<Utc>| ts.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let recent = |info: &PwxInfo| {
+        info.recent.iter().map(recid::base58).collect::<Vec<_>>().join(",")
+    };
+    let fields = [
+        ("uuid", old.uuid.clone(), new.uuid.clone()),
+        ("mtime", rfc3339(&old.mtime), rfc3339(&new.mtime)),
+        ("user", old.user.clone(), new.user.clone()),
+        ("host", old.host.clone(), new.host.clone()),
+        ("name", old.dbname.clone(), new.dbname.clone()),
+        ("description", old.description.clone(), new.description.clone()),
+        ("recent", recent(old), recent(new)),
+    ];
+    fields.iter().filter(|f| f.1 != f.2).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Value;
+
+    fn rec(uuid: Option<u8>, title: &str) -> Vec<Field> {
+        let mut rec = Vec::new();
+        if let Some(uuid) = uuid {
+            rec.push(Field::Uuid(Value::from(vec![uuid; 16])));
+        }
+        rec.push(Field::Title(Value::from(title.as_bytes().to_vec())));
+        rec
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![rec(Some(1), "a"), rec(Some(2), "b"), rec(Some(3), "c"), rec(None, "x"), rec(None, "y")];
+        let new = vec![rec(None, "y"), rec(Some(4), "d"), rec(Some(3), "c2"), rec(Some(1), "a"), rec(None, "z")];
+        assert!(diff(&old, &new) ==
+                vec![Change::Removed(&old[1]),
+                     Change::Changed(&old[2], &new[2]),
+                     Change::Removed(&old[3]),
+                     Change::Added(&new[1]),
+                     Change::Added(&new[4])]);
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_diff_field_order() {
+        let mut a = rec(Some(1), "a");
+        a.push(Field::Url(Value::from(vec![])));
+        let mut b = rec(Some(1), "a");
+        b.reverse();
+        assert!(diff(&[a.clone()], &[b.clone()]).is_empty());
+
+        let (mut a, mut b) = (rec(None, "a"), rec(None, "a"));
+        a.push(Field::Notes(Value::from(b"n".to_vec())));
+        b.insert(0, Field::Notes(Value::from(b"n".to_vec())));
+        assert!(diff(&[a], &[b]).is_empty());
+    }
+
+    #[test]
+    fn test_diff_duplicate_uuid() {
+        let old = vec![rec(Some(1), "a"), rec(Some(1), "b")];
+        let new = vec![rec(Some(1), "a"), rec(Some(1), "c")];
+        assert!(diff(&old, &new) == vec![Change::Removed(&old[1]), Change::Added(&new[1])]);
+    }
+
+    #[test]
+    fn test_diff_fields() {
+        let old = vec![Field::Uuid(Value::from(vec![1; 16])), Field::Title(Value::from(b"a".to_vec())),
+                       Field::Url(Value::from(vec![])), Field::Notes(Value::from(b"n".to_vec()))];
+        let new = vec![Field::Uuid(Value::from(vec![1; 16])), Field::Title(Value::from(b"b".to_vec())),
+                       Field::Email(Value::from(b"e".to_vec()))];
+        let changes = diff_fields(&old, &new);
+        assert_eq!(changes.iter().map(|c| c.typ).collect::<Vec<_>>(), vec![0x03, 0x05, 0x14]);
+        assert!(changes[0].old == Some(&old[1]) && changes[0].new == Some(&new[1]));
+        assert!(changes[1].old == Some(&old[3]) && changes[1].new.is_none());
+        assert!(changes[2].old.is_none() && changes[2].new == Some(&new[2]));
+        assert!(diff_fields(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_diff_fields_repeated() {
+        let field = |typ, val: &[u8]| Field::from(typ, Value::from(val.to_vec()));
+        let old = vec![field(0x40, b"x"), field(0x40, b"y"), field(0x03, b"t"), field(0x40, b"x")];
+        let new = vec![field(0x40, b"y"), field(0x03, b"t"), field(0x40, b"x"), field(0x40, b"x")];
+        assert!(diff_fields(&old, &new).is_empty());
+
+        let new = vec![field(0x40, b"z"), field(0x40, b"y"), field(0x03, b"t"), field(0x40, b"w")];
+        let changes = diff_fields(&old, &new);
+        assert_eq!(changes.len(), 2);
+        assert!(changes[0].old == Some(&old[0]) && changes[0].new == Some(&new[3]));
+        assert!(changes[1].old == Some(&old[3]) && changes[1].new == Some(&new[0]));
+
+        let new = vec![field(0x40, b"x"), field(0x40, b"y"), field(0x03, b"t")];
+        let changes = diff_fields(&old, &new);
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].old == Some(&old[0]) && changes[0].new.is_none());
+    }
+}
@@ -0,0 +1,529 @@
+//! Database filters saved by PasswordSafe
+//!
+//! The header field 0x0b holds named filters as XML, following the
+//! PasswordSafe filter schema. Each filter is a list of entries, one test
+//! per field, joined with and/or. As in PasswordSafe, `and` binds tighter
+//! than `or`.
+//!
+//! ```xml
+//! <filters>
+//!   <filter filtername="Work titles">
+//!     <filter_entry active="yes">
+//!       <group>
+//!         <rule>EQ</rule>
+//!         <logic>and</logic>
+//!         <test><string>Work</string><case>0</case></test>
+//!       </group>
+//!     </filter_entry>
+//!     ...
+//!   </filter>
+//! </filters>
+//! ```
+//!
+//! Text, date, integer and flag fields are supported, filters on the
+//! password history, password policy and entry type fail to parse.
+
+use super::Fail;
+use super::db::Field;
+use super::util::{read_uint, fold_case};
+use super::record;
+use super::chrono::naive::NaiveDate;
+use super::chrono::{DateTime, Duration, Local, Utc};
+use super::regex::Regex;
+use roxmltree;
+
+/// A named filter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub name: String,
+    pub expr: Expr,
+}
+
+/// A filter expression that can be evaluated against a record
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// Matches if all expressions match, or if empty
+    And(Vec<Expr>),
+    /// Matches if any expression matches
+    Or(Vec<Expr>),
+    /// Matches if the expression does not match
+    Not(Box<Expr>),
+    /// Test a field
+    Test(Selector, Test),
+}
+
+/// The record field(s) a test applies to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Selector {
+    /// A text field, by field type
+    Text(u8),
+    /// The group and title joined with a dot
+    GroupTitle,
+    /// A time_t field, by field type
+    Time(u8),
+    /// The password expiry, explicit or from the expiry interval, see
+    /// `record::expiry()`
+    Expiry,
+    /// An unsigned integer field, by field type
+    Integer(u8),
+    /// The total size of all fields in the record
+    Size,
+    /// A one byte flag field, by field type
+    Flag(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextOp {
+    Equals,
+    NotEqual,
+    Begins,
+    NotBegin,
+    Ends,
+    NotEnd,
+    Contains,
+    NotContain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Equals,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Between,
+}
+
+/// A regular expression, compared by its source
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+/// A date in a date test, either a fixed date or a number of days
+/// relative to the current date. Dates are in the local time zone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateBound {
+    Absolute(NaiveDate),
+    Relative(i64),
+}
+
+impl DateBound {
+    fn resolve(&self, now: &DateTime<Utc>) -> NaiveDate {
+        match *self {
+            DateBound::Absolute(d) => d,
+            DateBound::Relative(days) => local_date(&(*now + Duration::days(days))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Test {
+    /// The field is present and not empty
+    Present,
+    NotPresent,
+    /// Compare text, `case` is true for case sensitive matches
+    Text { op: TextOp, value: String, case: bool },
+    /// The text matches a regular expression, absent fields are empty
+    Regex(Pattern),
+    /// Compare with one integer or, for `Between`, two
+    Integer { op: CmpOp, a: i64, b: i64 },
+    /// Compare dates, with day granularity
+    Date { op: CmpOp, a: DateBound, b: DateBound },
+    /// The date is in the past
+    Expired,
+    /// The date is in the next N days
+    ExpiresWithin(i64),
+    /// A flag is set
+    Set,
+    NotSet,
+}
+
+fn text(record: &[Field], sel: Selector) -> Option<String> {
+    match sel {
+        Selector::Text(typ) => {
+            record::find(record, typ).map(|f| String::from_utf8_lossy(f.value().as_ref()).into_owned())
+        }
+        Selector::GroupTitle => {
+            let group = text(record, Selector::Text(0x02));
+            let title = text(record, Selector::Text(0x03));
+            match (group, title) {
+                (Some(g), Some(t)) => Some(format!("{}.{}", g, t)),
+                (Some(g), None) => Some(g),
+                (None, t) => t,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn integer(record: &[Field], sel: Selector) -> Option<i64> {
+    match sel {
+        Selector::Integer(typ) |
+        Selector::Flag(typ) => {
+            // A zero value is the same as an absent field
+            record::find(record, typ)
+                .and_then(|f| read_uint(f.value().as_ref()))
+                .map(|v| v as i64)
+                .filter(|v| *v != 0)
+        }
+        Selector::Size => Some(record.iter().map(|f| f.value().as_ref().len() as i64).sum()),
+        _ => None,
+    }
+}
+
+fn local_date(ts: &DateTime<Utc>) -> NaiveDate {
+    ts.with_timezone(&Local).date_naive()
+}
+
+fn time(record: &[Field], sel: Selector) -> Option<DateTime<Utc>> {
+    match sel {
+        Selector::Time(typ) => record::time(record, typ),
+        Selector::Expiry => record::expiry(record),
+        _ => None,
+    }
+}
+
+fn compare<T: PartialOrd>(op: CmpOp, val: T, a: T, b: T) -> bool {
+    match op {
+        CmpOp::Equals => val == a,
+        CmpOp::NotEqual => val != a,
+        CmpOp::Less => val < a,
+        CmpOp::LessEqual => val <= a,
+        CmpOp::Greater => val > a,
+        CmpOp::GreaterEqual => val >= a,
+        CmpOp::Between => val >= a && val <= b,
+    }
+}
+
+fn is_present(record: &[Field], sel: Selector) -> bool {
+    match sel {
+        Selector::Text(_) | Selector::GroupTitle => text(record, sel).is_some(),
+        Selector::Time(_) | Selector::Expiry => time(record, sel).is_some(),
+        Selector::Integer(_) | Selector::Size | Selector::Flag(_) => integer(record, sel).is_some(),
+    }
+}
+
+impl Expr {
+    /// Evaluate the expression for a record, relative dates are computed
+    /// from `now`
+    pub fn matches(&self, record: &[Field], now: &DateTime<Utc>) -> bool {
+        match *self {
+            Expr::And(ref exprs) => exprs.iter().all(|e| e.matches(record, now)),
+            Expr::Or(ref exprs) => exprs.iter().any(|e| e.matches(record, now)),
+            Expr::Not(ref expr) => !expr.matches(record, now),
+            Expr::Test(sel, ref test) => test.matches(record, sel, now),
+        }
+    }
+}
+
+impl Test {
+    fn matches(&self, record: &[Field], sel: Selector, now: &DateTime<Utc>) -> bool {
+        match *self {
+            Test::Present => is_present(record, sel),
+            Test::NotPresent => !is_present(record, sel),
+            Test::Text { op, ref value, case } => {
+                let hay = text(record, sel).unwrap_or_default();
+                let (hay, needle) = if case {
+                    (hay, value.clone())
+                } else {
+                    (fold_case(&hay, false), fold_case(value, false))
+                };
+                match op {
+                    TextOp::Equals => hay == needle,
+                    TextOp::NotEqual => hay != needle,
+                    TextOp::Begins => hay.starts_with(&needle),
+                    TextOp::NotBegin => !hay.starts_with(&needle),
+                    TextOp::Ends => hay.ends_with(&needle),
+                    TextOp::NotEnd => !hay.ends_with(&needle),
+                    TextOp::Contains => hay.contains(&needle),
+                    TextOp::NotContain => !hay.contains(&needle),
+                }
+            }
+            Test::Regex(ref re) => re.0.is_match(&text(record, sel).unwrap_or_default()),
+            Test::Integer { op, a, b } => {
+                match integer(record, sel) {
+                    Some(val) => compare(op, val, a, b),
+                    None => op == CmpOp::NotEqual,
+                }
+            }
+            Test::Date { op, a, b } => {
+                match time(record, sel) {
+                    Some(ts) => compare(op, local_date(&ts), a.resolve(now), b.resolve(now)),
+                    None => op == CmpOp::NotEqual,
+                }
+            }
+            Test::Expired => time(record, sel).map(|ts| ts <= *now).unwrap_or(false),
+            Test::ExpiresWithin(days) => {
+                time(record, sel)
+                    .map(|ts| ts > *now && ts <= *now + Duration::days(days))
+                    .unwrap_or(false)
+            }
+            Test::Set => integer(record, sel).is_some(),
+            Test::NotSet => integer(record, sel).is_none(),
+        }
+    }
+}
+
+fn invalid(msg: String) -> Fail {
+    Fail::InvalidFilter(msg)
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name(name))
+        .map(|n| n.text().unwrap_or("").trim())
+}
+
+fn child_int(node: roxmltree::Node, name: &str) -> Result<i64, Fail> {
+    let s = child_text(node, name).unwrap_or("0");
+    s.parse().map_err(|_| invalid(format!("<{}> is not a number: {}", name, s)))
+}
+
+fn child_date(node: roxmltree::Node, date: &str, num: &str) -> Result<DateBound, Fail> {
+    match child_text(node, date) {
+        Some(s) if !s.is_empty() => {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .map(DateBound::Absolute)
+                .map_err(|_| invalid(format!("<{}> is not a date: {}", date, s)))
+        }
+        _ => child_int(node, num).map(DateBound::Relative),
+    }
+}
+
+/// Map a filter schema element name to a field selector
+fn selector(name: &str) -> Option<Selector> {
+    let sel = match name {
+        "group" => Selector::Text(0x02),
+        "title" => Selector::Text(0x03),
+        "user" => Selector::Text(0x04),
+        "notes" => Selector::Text(0x05),
+        "password" => Selector::Text(0x06),
+        "url" => Selector::Text(0x0d),
+        "autotype" => Selector::Text(0x0e),
+        "runcommand" | "run_command" => Selector::Text(0x12),
+        "email" => Selector::Text(0x14),
+        "symbols" => Selector::Text(0x16),
+        "policy_name" => Selector::Text(0x18),
+        "grouptitle" | "group_title" => Selector::GroupTitle,
+        "create_time" => Selector::Time(0x07),
+        "password_modified_time" => Selector::Time(0x08),
+        "last_access_time" => Selector::Time(0x09),
+        "expiry_time" => Selector::Expiry,
+        "record_modified_time" => Selector::Time(0x0c),
+        "password_expiry_interval" => Selector::Integer(0x11),
+        "entrysize" | "entry_size" => Selector::Size,
+        "protected" => Selector::Flag(0x15),
+        _ => return None,
+    };
+    Some(sel)
+}
+
+/// Parse a single filter entry, returns the test and if it is joined
+/// to the previous entry with `and`
+fn parse_entry(field: roxmltree::Node) -> Result<(Expr, bool), Fail> {
+    let name = field.tag_name().name();
+    let sel = selector(name).ok_or_else(|| invalid(format!("Unsupported filter field: {}", name)))?;
+    let rule = child_text(field, "rule").unwrap_or("");
+    let and = child_text(field, "logic").map(|l| !l.eq_ignore_ascii_case("or")).unwrap_or(true);
+    let params = field.children().find(|n| n.has_tag_name("test"));
+
+    let test = match (rule, params) {
+        ("PR", _) => Test::Present,
+        ("NP", _) => Test::NotPresent,
+        (rule, Some(params)) => {
+            match sel {
+                Selector::Text(_) | Selector::GroupTitle => {
+                    let op = match rule {
+                        "EQ" => TextOp::Equals,
+                        "NE" => TextOp::NotEqual,
+                        "BE" => TextOp::Begins,
+                        "NB" => TextOp::NotBegin,
+                        "EN" => TextOp::Ends,
+                        "ND" => TextOp::NotEnd,
+                        "CO" => TextOp::Contains,
+                        "NC" => TextOp::NotContain,
+                        _ => return Err(invalid(format!("Unsupported rule for {}: {}", name, rule))),
+                    };
+                    Test::Text {
+                        op,
+                        value: child_text(params, "string").unwrap_or("").to_owned(),
+                        case: child_text(params, "case") == Some("1"),
+                    }
+                }
+                Selector::Time(_) | Selector::Expiry => {
+                    let op = match rule {
+                        "EX" => return Ok((Expr::Test(sel, Test::Expired), and)),
+                        "WX" => {
+                            let days = child_int(params, "num1")?;
+                            return Ok((Expr::Test(sel, Test::ExpiresWithin(days)), and));
+                        }
+                        "EQ" => CmpOp::Equals,
+                        "NE" => CmpOp::NotEqual,
+                        "LT" | "BF" => CmpOp::Less,
+                        "LE" => CmpOp::LessEqual,
+                        "GT" | "AF" => CmpOp::Greater,
+                        "GE" => CmpOp::GreaterEqual,
+                        "BT" => CmpOp::Between,
+                        _ => return Err(invalid(format!("Unsupported rule for {}: {}", name, rule))),
+                    };
+                    Test::Date {
+                        op,
+                        a: child_date(params, "date1", "num1")?,
+                        b: child_date(params, "date2", "num2")?,
+                    }
+                }
+                Selector::Integer(_) | Selector::Size => {
+                    let op = match rule {
+                        "EQ" => CmpOp::Equals,
+                        "NE" => CmpOp::NotEqual,
+                        "LT" => CmpOp::Less,
+                        "LE" => CmpOp::LessEqual,
+                        "GT" => CmpOp::Greater,
+                        "GE" => CmpOp::GreaterEqual,
+                        "BT" => CmpOp::Between,
+                        _ => return Err(invalid(format!("Unsupported rule for {}: {}", name, rule))),
+                    };
+                    Test::Integer {
+                        op,
+                        a: child_int(params, "num1")?,
+                        b: child_int(params, "num2")?,
+                    }
+                }
+                Selector::Flag(_) => {
+                    match rule {
+                        "IS" | "SE" => Test::Set,
+                        "NI" | "NS" => Test::NotSet,
+                        _ => return Err(invalid(format!("Unsupported rule for {}: {}", name, rule))),
+                    }
+                }
+            }
+        }
+        (rule, None) => {
+            match (sel, rule) {
+                (Selector::Flag(_), "IS") | (Selector::Flag(_), "SE") => Test::Set,
+                (Selector::Flag(_), "NI") | (Selector::Flag(_), "NS") => Test::NotSet,
+                (_, "EX") => Test::Expired,
+                _ => return Err(invalid(format!("Missing <test> for {}", name))),
+            }
+        }
+    };
+    Ok((Expr::Test(sel, test), and))
+}
+
+fn parse_filter(node: roxmltree::Node) -> Result<Filter, Fail> {
+    let name = node.attribute("filtername").unwrap_or("").to_owned();
+
+    // Entries joined with `and` are grouped, the groups are joined with `or`
+    let mut groups: Vec<Vec<Expr>> = Vec::new();
+    for entry in node.children().filter(|n| n.has_tag_name("filter_entry")) {
+        if entry.attribute("active") == Some("no") {
+            continue;
+        }
+        let field = entry.children()
+            .find(|n| n.is_element())
+            .ok_or_else(|| invalid(format!("Empty filter entry in {}", name)))?;
+        let (expr, and) = parse_entry(field)?;
+        match groups.last_mut() {
+            Some(group) if and => group.push(expr),
+            _ => groups.push(vec![expr]),
+        }
+    }
+
+    let expr = match groups.len() {
+        0 => Expr::And(Vec::new()),
+        1 => Expr::And(groups.remove(0)),
+        _ => Expr::Or(groups.into_iter().map(Expr::And).collect()),
+    };
+    Ok(Filter { name, expr })
+}
+
+/// Parse the filters XML stored in the database header (0x0b)
+pub fn parse_filters(xml: &str) -> Result<Vec<Filter>, Fail> {
+    let doc = roxmltree::Document::parse(xml).map_err(|err| invalid(err.to_string()))?;
+    let root = doc.root_element();
+    if root.has_tag_name("filter") {
+        return Ok(vec![parse_filter(root)?]);
+    }
+    root.children()
+        .filter(|n| n.has_tag_name("filter"))
+        .map(parse_filter)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Value;
+    use chrono::TimeZone;
+
+    fn record(fields: &[(u8, &[u8])]) -> Vec<Field> {
+        fields.iter().map(|&(typ, val)| Field::from(typ, Value::from(val.to_vec()))).collect()
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2017, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<filters>
+<filter filtername="Work">
+<filter_entry active="yes"><group><rule>EQ</rule><logic>and</logic><test><string>work</string><case>0</case></test></group></filter_entry>
+<filter_entry active="yes"><title><rule>CO</rule><logic>and</logic><test><string>git</string><case>1</case></test></title></filter_entry>
+<filter_entry active="yes"><url><rule>PR</rule><logic>or</logic></url></filter_entry>
+<filter_entry active="no"><title><rule>EQ</rule><logic>and</logic><test><string>nothing</string><case>0</case></test></title></filter_entry>
+</filter>
+<filter filtername="Expiring soon">
+<filter_entry active="yes"><expiry_time><rule>WX</rule><logic>and</logic><test><num1>30</num1></test></expiry_time></filter_entry>
+</filter>
+<filter filtername="Old">
+<filter_entry active="yes"><create_time><rule>BF</rule><logic>and</logic><test><date1>2016-01-01</date1></test></create_time></filter_entry>
+<filter_entry active="yes"><protected><rule>NI</rule><logic>and</logic></protected></filter_entry>
+</filter>
+</filters>"#;
+
+    #[test]
+    fn test_parse_filters() {
+        let filters = parse_filters(XML).unwrap();
+        assert_eq!(filters.len(), 3);
+        assert_eq!(filters[0].name, "Work");
+        assert_eq!(filters[1].name, "Expiring soon");
+
+        let work = &filters[0].expr;
+        assert!(work.matches(&record(&[(0x02, b"Work"), (0x03, b"github")]), &now()));
+        assert!(!work.matches(&record(&[(0x02, b"Work"), (0x03, b"GitHub")]), &now()));
+        assert!(!work.matches(&record(&[(0x02, b"Home"), (0x03, b"github")]), &now()));
+        assert!(work.matches(&record(&[(0x02, b"Home"), (0x0d, b"https://x")]), &now()));
+        assert!(!work.matches(&record(&[(0x02, b"Home"), (0x0d, b"")]), &now()));
+
+        // 2017-06-10 and 2017-08-01
+        let expiring = &filters[1].expr;
+        assert!(expiring.matches(&record(&[(0x0a, &[0x80, 0x39, 0x3b, 0x59])]), &now()));
+        assert!(!expiring.matches(&record(&[(0x0a, &[0x00, 0x58, 0x7f, 0x59])]), &now()));
+        assert!(!expiring.matches(&record(&[(0x0a, &[0, 0, 0, 0])]), &now()));
+        assert!(!expiring.matches(&record(&[]), &now()));
+        // The password expiry interval counts, 2017-05-15 + 30 and 90 days
+        let ptime = [0x80, 0xef, 0x18, 0x59];
+        assert!(expiring.matches(&record(&[(0x08, &ptime), (0x11, &[30, 0])]), &now()));
+        assert!(!expiring.matches(&record(&[(0x08, &ptime), (0x11, &[90, 0])]), &now()));
+
+        // 2015-06-06
+        let old = &filters[2].expr;
+        assert!(old.matches(&record(&[(0x07, &[0x80, 0x39, 0x72, 0x55])]), &now()));
+        assert!(!old.matches(&record(&[(0x07, &[0x80, 0x39, 0x72, 0x55]), (0x15, &[1])]), &now()));
+        assert!(!old.matches(&record(&[(0x07, &[0x80, 0x39, 0x3b, 0x59])]), &now()));
+    }
+
+    #[test]
+    fn test_parse_filters_invalid() {
+        assert!(parse_filters("<filters>").is_err());
+        assert!(parse_filters(r#"<filter filtername="x"><filter_entry><entrytype><rule>IS</rule></entrytype></filter_entry></filter>"#).is_err());
+        assert!(parse_filters(r#"<filter filtername="x"><filter_entry><title><rule>XX</rule><test></test></title></filter_entry></filter>"#).is_err());
+        assert_eq!(parse_filters("<filters/>").unwrap(), vec![]);
+    }
+}
@@ -7,30 +7,72 @@ extern crate chrono;
 extern crate twofish as crypto_twofish;
 extern crate sha2;
 extern crate hmac;
-
-use std::fs::File;
-use std::path::Path;
+extern crate rand;
+extern crate roxmltree;
+extern crate serde;
+extern crate serde_json;
+extern crate sha1;
+extern crate base32;
+extern crate rust_base58;
+extern crate regex;
+extern crate unicode_normalization;
+extern crate unicode_width;
+
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
 use std::io;
-use std::io::{Seek, Read};
+use std::io::{Seek, Read, Write, BufWriter};
 use std::fmt;
 use sha2::Sha256;
 use sha2::Digest;
 use hmac::{Hmac, Mac};
 use std::cmp::min;
 use secstr::SecStr;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use uuid::Uuid;
-use chrono::naive::NaiveDateTime;
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use rand::rngs::OsRng;
 
 mod twofish;
 use twofish::Key;
 
 pub mod util;
-use util::{stretch_pass, from_time_t};
+use util::{stretch_pass, from_time_t, to_time_t, parse_recent};
 
 pub mod db;
 pub use db::{Field, Value};
 
+pub mod record;
+
+pub mod filter;
+use filter::{Filter, parse_filters};
+
+pub mod run;
+
+pub mod autotype;
+
+pub mod totp;
+
+pub mod card;
+
+pub mod action;
+
+pub mod recid;
+
+pub mod query;
+
+pub mod rank;
+
+pub mod table;
+
+pub mod output;
+
+pub mod template;
+
+pub mod diff;
+
 const PREAMBLE_SIZE: usize = 152;
 const SHA256_SIZE: usize = 32;
 const BLOCK_SIZE: usize = 16;
@@ -39,12 +81,24 @@ const BLOCK_SIZE: usize = 16;
 pub enum Fail {
     UnableToOpen(io::Error),
     ReadError(io::Error),
+    WriteError(io::Error),
     InvalidTag,
     InvalidIterationCount,
     WrongPassword,
     AuthenticationFailed,
     UnableToInitializeTwofishKey,
     InvalidSalt,
+    InvalidFilter(String),
+    ProtectedRecord,
+    UnsupportedKey(String),
+    InvalidTotp(String),
+    MissingField(String),
+    ActionFailed(String),
+    InvalidValue(String),
+    UnknownRecord(String),
+    AmbiguousRecord(String, Vec<String>),
+    InvalidQuery(String),
+    InvalidTemplate(String),
     EOF,
 }
 
@@ -59,6 +113,7 @@ impl fmt::Display for Fail {
         match *self {
             Fail::UnableToOpen(ref s) => s.fmt(fmt),
             Fail::ReadError(ref s) => s.fmt(fmt),
+            Fail::WriteError(ref s) => s.fmt(fmt),
             Fail::InvalidTag => fmt.write_str("Invalid DB Tag"),
             Fail::InvalidIterationCount => fmt.write_str("Invalid DB, iteration count is too low"),
             Fail::WrongPassword => fmt.write_str("Wrong Password for DB"),
@@ -69,6 +124,23 @@ impl fmt::Display for Fail {
                 fmt.write_str("libtwofish failed to initialize a key from the given data")
             }
             Fail::InvalidSalt => fmt.write_str("Salt is too short"),
+            Fail::InvalidFilter(ref s) => write!(fmt, "Invalid filter: {}", s),
+            Fail::ProtectedRecord => fmt.write_str("The record is protected"),
+            Fail::UnsupportedKey(ref s) => write!(fmt, "Unsupported key: {}", s),
+            Fail::InvalidTotp(ref s) => write!(fmt, "Invalid TOTP: {}", s),
+            Fail::MissingField(ref s) => write!(fmt, "Record has no {}", s),
+            Fail::ActionFailed(ref s) => write!(fmt, "Action failed: {}", s),
+            Fail::InvalidValue(ref s) => write!(fmt, "Invalid value: {}", s),
+            Fail::InvalidQuery(ref s) => write!(fmt, "Invalid query: {}", s),
+            Fail::InvalidTemplate(ref s) => write!(fmt, "Invalid format string: {}", s),
+            Fail::UnknownRecord(ref s) => write!(fmt, "Unknown record: {}", s),
+            Fail::AmbiguousRecord(ref s, ref candidates) => {
+                write!(fmt, "Ambiguous record id {}, candidates:", s)?;
+                for candidate in candidates {
+                    write!(fmt, "\n  {}", candidate)?;
+                }
+                Ok(())
+            }
             Fail::EOF => fmt.write_str("EOF"),
         }
     }
@@ -80,7 +152,7 @@ impl fmt::Display for Fail {
 pub struct PwxInfo {
     pub uuid: String,
     /// Last save time
-    pub mtime: NaiveDateTime,
+    pub mtime: DateTime<Utc>,
     /// Last saved by user
     pub user: String,
     /// Last saved on host
@@ -89,6 +161,8 @@ pub struct PwxInfo {
     pub dbname: String,
     /// Database description
     pub description: String,
+    /// Recently used entries, the most recent first
+    pub recent: Vec<Uuid>,
 }
 
 /// Holds Key info and the HMAC retrieved from the
@@ -102,6 +176,9 @@ pub struct PwxKeyInfo {
     iv: [u8; BLOCK_SIZE],
     /// The iteration count for password stretching
     iter: u32,
+    /// The preamble up to the IV, i.e. TAG|SALT|ITER|H(P')|B1|B2|B3|B4,
+    /// kept to save the DB with the same keys
+    preamble: [u8; PREAMBLE_SIZE - BLOCK_SIZE],
 }
 
 impl PwxKeyInfo {
@@ -171,11 +248,15 @@ impl PwxKeyInfo {
             arr[i] = iv[i];
         }
 
+        let mut keyblock = [0u8; PREAMBLE_SIZE - BLOCK_SIZE];
+        keyblock.copy_from_slice(&preamble[..PREAMBLE_SIZE - BLOCK_SIZE]);
+
         Ok(PwxKeyInfo {
             key_k: key_k,
             iter: itercount,
             iv: arr,
             key_l: l_bin,
+            preamble: keyblock,
         })
     }
 
@@ -341,9 +422,122 @@ impl<'a, 'b, R: Read> Iterator for PwxRecordIter<'a, 'b, R> {
     }
 }
 
+/// Encrypts fields and writes them to a sink, this is the counterpart
+/// of `PwxFieldIter`.
+///
+/// The preamble is written when the writer is created, call `finish()`
+/// after the last field to write the EOF block and the HMAC.
+pub struct PwxWriter<'a, W> {
+    keys: &'a PwxKeyInfo,
+    sink: W,
+    cbc_block: [u8; BLOCK_SIZE],
+    hmac: Hmac<Sha256>,
+}
+
+impl<'a, W: Write> PwxWriter<'a, W> {
+    /// Write the preamble using the given keys, and a new IV
+    pub fn new(keys: &'a PwxKeyInfo, iv: &[u8; BLOCK_SIZE], mut sink: W) -> Result<PwxWriter<'a, W>, Fail> {
+        sink.write_all(&keys.preamble).map_err(Fail::WriteError)?;
+        sink.write_all(iv).map_err(Fail::WriteError)?;
+        Ok(PwxWriter {
+            keys,
+            sink,
+            cbc_block: *iv,
+            hmac: keys.hmac(),
+        })
+    }
+
+    /// Encrypt and write a single block
+    fn write_block(&mut self, block: &[u8]) -> Result<(), Fail> {
+        let mut plain = SecStr::new(vec![0u8; BLOCK_SIZE]);
+        {
+            let plain_r = plain.unsecure_mut();
+            for i in 0..BLOCK_SIZE {
+                plain_r[i] = block[i] ^ self.cbc_block[i];
+            }
+        }
+        self.keys.key_k.encrypt(plain.unsecure(), &mut self.cbc_block);
+        self.sink.write_all(&self.cbc_block).map_err(Fail::WriteError)
+    }
+
+    /// Write a field, the unused bytes in the last block are filled
+    /// with random values. [sec. 3]
+    pub fn write_field(&mut self, typ: u8, data: &[u8]) -> Result<(), Fail> {
+        let blocks = (data.len() + 5).div_ceil(BLOCK_SIZE);
+        let mut field_memory = SecStr::new(vec![0u8; blocks * BLOCK_SIZE]);
+        {
+            let field_r = field_memory.unsecure_mut();
+            OsRng.fill_bytes(field_r);
+            (&mut field_r[..4]).write_u32::<LittleEndian>(data.len() as u32)
+                .map_err(Fail::WriteError)?;
+            field_r[4] = typ;
+            field_r[5..5 + data.len()].clone_from_slice(data);
+        }
+        for block in field_memory.unsecure().chunks(BLOCK_SIZE) {
+            self.write_block(block)?;
+        }
+        if !data.is_empty() {
+            self.hmac.update(data);
+        }
+        Ok(())
+    }
+
+    /// Write all fields in a record followed by the end of entry field
+    pub fn write_record(&mut self, record: &[Field]) -> Result<(), Fail> {
+        for field in record {
+            self.write_field(field.typ(), field.value().as_ref())?;
+        }
+        self.write_field(0xff, &[])
+    }
+
+    /// Write the EOF block and the HMAC, returns the underlying sink
+    pub fn finish(mut self) -> Result<W, Fail> {
+        self.sink.write_all(b"PWS3-EOFPWS3-EOF").map_err(Fail::WriteError)?;
+        let result = self.hmac.finalize().into_bytes();
+        self.sink.write_all(result.as_slice()).map_err(Fail::WriteError)?;
+        self.sink.flush().map_err(Fail::WriteError)?;
+        Ok(self.sink)
+    }
+}
+
+/// Create the temporary file for `PwxReader::save()` next to the
+/// database, `<db>.<pid>.<random>.tmp`, with the permissions of the
+/// database. Existing files are never reused, symlinks are not followed,
+/// and a name that is taken (e.g. left by an interrupted save) is
+/// retried with another random part.
+fn create_tmp(path: &Path, db: &File) -> Result<(PathBuf, File), Fail> {
+    let perms = db.metadata().map(|meta| meta.permissions()).map_err(Fail::WriteError)?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(perms.mode() & 0o777);
+    }
+
+    let mut attempts = 0;
+    let (tmppath, file) = loop {
+        let mut tmppath = path.as_os_str().to_owned();
+        tmppath.push(format!(".{}.{:08x}.tmp", std::process::id(), OsRng.next_u32()));
+        let tmppath = PathBuf::from(tmppath);
+        match options.open(&tmppath) {
+            Ok(file) => break (tmppath, file),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists && attempts < 10 => attempts += 1,
+            Err(err) => return Err(Fail::WriteError(err)),
+        }
+    };
+    // The mode given to open() is subject to the umask
+    if let Err(err) = file.set_permissions(perms) {
+        let _ = fs::remove_file(&tmppath);
+        return Err(Fail::WriteError(err));
+    }
+    Ok((tmppath, file))
+}
+
 pub struct PwxReader {
     keys: PwxKeyInfo,
     file: File,
+    path: PathBuf,
 }
 
 impl PwxReader {
@@ -361,6 +555,7 @@ impl PwxReader {
         Ok(PwxReader {
             file: file,
             keys: PwxKeyInfo::parse_preamble(&preamble, password)?,
+            path: path.to_path_buf(),
         })
     }
 
@@ -400,15 +595,101 @@ impl PwxReader {
         Ok(PwxRecordIter { fielditer: fielditer })
     }
 
+    /// Returns the raw header fields, without the end of entry field
+    pub fn header(&mut self) -> Result<Vec<(u8, Value)>, Fail> {
+        let mut header = Vec::new();
+        for f in self.fields()? {
+            let (typ, val) = f?;
+            if typ == 0xff {
+                break;
+            }
+            header.push((typ, val));
+        }
+        Ok(header)
+    }
+
+    /// Save the database, replacing its header and records.
+    ///
+    /// The database keeps the same password and keys, only the IV is
+    /// regenerated. The last save time (0x04) and application (0x06)
+    /// header fields are updated. The new database is written to a
+    /// temporary file and then renamed over the original file. The
+    /// temporary file must not exist, it is removed if the save fails.
+    pub fn save(&mut self, header: &[(u8, Value)], records: &[Vec<Field>]) -> Result<(), Fail> {
+        let mut iv = [0u8; BLOCK_SIZE];
+        OsRng.fill_bytes(&mut iv);
+
+        let (tmppath, file) = create_tmp(&self.path, &self.file)?;
+        let written = self.write_to(file, &iv, header, records)
+            .and_then(|_| fs::rename(&tmppath, &self.path).map_err(Fail::WriteError));
+        if let Err(err) = written {
+            let _ = fs::remove_file(&tmppath);
+            return Err(err);
+        }
+        self.file = File::open(&self.path).map_err(Fail::UnableToOpen)?;
+        self.keys.iv = iv;
+        Ok(())
+    }
+
+    /// Write the database with the given IV to `file`, for `save()`
+    fn write_to(&self, file: File, iv: &[u8; BLOCK_SIZE], header: &[(u8, Value)], records: &[Vec<Field>])
+            -> Result<(), Fail> {
+        let now = to_time_t(&Utc::now())?;
+        let app = format!("pwx V{}", env!("CARGO_PKG_VERSION"));
+        let mut w = PwxWriter::new(&self.keys, iv, BufWriter::new(file))?;
+
+        let mut saved_time = false;
+        let mut saved_app = false;
+        for &(typ, ref val) in header {
+            match typ {
+                0x04 => {
+                    w.write_field(typ, &now)?;
+                    saved_time = true;
+                }
+                0x06 => {
+                    w.write_field(typ, app.as_bytes())?;
+                    saved_app = true;
+                }
+                _ => w.write_field(typ, val.as_ref())?,
+            }
+        }
+        if !saved_time {
+            w.write_field(0x04, &now)?;
+        }
+        if !saved_app {
+            w.write_field(0x06, app.as_bytes())?;
+        }
+        w.write_field(0xff, &[])?;
+
+        for record in records {
+            w.write_record(record)?;
+        }
+        let file = w.finish()?
+            .into_inner()
+            .map_err(|err| Fail::WriteError(err.into()))?;
+        file.sync_all().map_err(Fail::WriteError)
+    }
+
+    /// Returns the filters saved in the database header
+    pub fn filters(&mut self) -> Result<Vec<Filter>, Fail> {
+        for (typ, val) in self.header()? {
+            if typ == 0x0b && !val.as_ref().is_empty() {
+                return parse_filters(&String::from_utf8_lossy(val.as_ref()));
+            }
+        }
+        Ok(Vec::new())
+    }
+
     /// Returns database header info
     pub fn info(&mut self) -> Result<PwxInfo, Fail> {
         let mut info = PwxInfo {
             uuid: String::new(),
-            mtime: NaiveDateTime::from_timestamp(0, 0),
+            mtime: DateTime::default(),
             user: String::new(),
             host: String::new(),
             dbname: String::new(),
             description: String::new(),
+            recent: Vec::new(),
         };
 
         for f in self.fields()? {
@@ -421,13 +702,13 @@ impl PwxReader {
                                     .to_string()
                 }
                 0x04 => {
-                    info.mtime = from_time_t(val.as_ref())
-                                     .unwrap_or(NaiveDateTime::from_timestamp(0, 0))
+                    info.mtime = from_time_t(val.as_ref()).unwrap_or_default()
                 }
                 0x07 => info.user = String::from_utf8_lossy(val.as_ref()).into_owned(),
                 0x08 => info.host = String::from_utf8_lossy(val.as_ref()).into_owned(),
                 0x09 => info.dbname = String::from_utf8_lossy(val.as_ref()).into_owned(),
                 0x0a => info.description = String::from_utf8_lossy(val.as_ref()).into_owned(),
+                0x0f => info.recent = parse_recent(val.as_ref()).unwrap_or_default(),
                 0xff => break,
                 _ => (),
             }
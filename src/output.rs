@@ -0,0 +1,195 @@
+//! Structured output, e.g. for `pwx list --output json`
+//!
+//! A record is an object with its `id` (base58) and all named fields,
+//! see `Field::name()`. Timestamps are RFC 3339 strings in UTC, the
+//! protected flag is a boolean and numeric fields are numbers. Secret
+//! fields (password, two-factor key and card secrets) are left out unless
+//! asked for. CSV and TSV have a header line with the column names.
+
+use super::db::Field;
+use super::record;
+use super::recid;
+use super::util::{from_time_t, read_uint};
+use super::chrono::SecondsFormat;
+use super::serde_json::{Map, Value};
+
+/// Output formats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Format strings, one record per line
+    Text,
+    /// A JSON array
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    Csv,
+    Tsv,
+    /// Format strings, each record terminated by NUL instead of newline
+    Nul,
+}
+
+impl Format {
+    /// Parse a format name, e.g. `jsonl`
+    pub fn from_name(name: &str) -> Option<Format> {
+        let format = match name {
+            "text" => Format::Text,
+            "json" => Format::Json,
+            "jsonl" => Format::Jsonl,
+            "csv" => Format::Csv,
+            "tsv" => Format::Tsv,
+            "nul" => Format::Nul,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Returns true for json, jsonl, csv and tsv
+    pub fn is_structured(&self) -> bool {
+        !matches!(*self, Format::Text | Format::Nul)
+    }
+}
+
+/// Field types that are only included on request
+pub const SECRET_FIELDS: &[u8] = &[0x06, 0x1b, 0x1c, 0x1e, 0x1f];
+
+/// The columns of a record, `id` followed by the field names in type order
+pub fn columns(secrets: bool) -> Vec<&'static str> {
+    let mut columns = vec!["id"];
+    for typ in 0x01..=0xfe {
+        if !secrets && SECRET_FIELDS.contains(&typ) {
+            continue;
+        }
+        if let Some(name) = Field::from(typ, vec![].into()).name() {
+            columns.push(name);
+        }
+    }
+    columns
+}
+
+/// The typed JSON value of a field
+pub fn field_value(field: &Field) -> Value {
+    let val = field.value().as_ref();
+    match field.typ() {
+        0x07..=0x0a | 0x0c | 0x24 => {
+            match from_time_t(val) {
+                Ok(ts) => Value::from(ts.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                Err(_) => Value::Null,
+            }
+        }
+        0x11 | 0x21 | 0x22 | 0x23 => read_uint(val).map(Value::from).unwrap_or(Value::Null),
+        0x15 => Value::from(val.iter().any(|b| *b != 0)),
+        // Display masks card secrets
+        0x1c | 0x1e | 0x1f => Value::from(String::from_utf8_lossy(val).into_owned()),
+        _ => Value::from(field.to_string()),
+    }
+}
+
+/// A record as a JSON object
+pub fn record_json(rec: &[Field], secrets: bool) -> Value {
+    let mut obj = Map::new();
+    if let Some(uuid) = record::uuid(rec) {
+        obj.insert("id".to_owned(), Value::from(recid::base58(&uuid)));
+    }
+    for field in rec {
+        if !secrets && SECRET_FIELDS.contains(&field.typ()) {
+            continue;
+        }
+        if let Some(name) = field.name() {
+            obj.insert(name.to_owned(), field_value(field));
+        }
+    }
+    Value::Object(obj)
+}
+
+/// A value of a JSON object as text, e.g. for CSV. Strings are not
+/// quoted, missing values and null are empty.
+pub fn cell(obj: &Value, column: &str) -> String {
+    match obj.get(column) {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(other) => other.to_string(),
+    }
+}
+
+/// The values of a record for the given columns, as text
+pub fn record_row(rec: &[Field], columns: &[&str], secrets: bool) -> Vec<String> {
+    let obj = record_json(rec, secrets);
+    columns.iter().map(|c| cell(&obj, c)).collect()
+}
+
+/// Format a CSV line (RFC 4180), values with commas, quotes or line
+/// breaks are quoted
+pub fn csv_row<S: AsRef<str>>(values: &[S]) -> String {
+    let values: Vec<String> = values.iter()
+        .map(|v| {
+            let v = v.as_ref();
+            if v.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", v.replace('"', "\"\""))
+            } else {
+                v.to_owned()
+            }
+        })
+        .collect();
+    values.join(",")
+}
+
+/// Format a TSV line, tabs, line breaks and backslashes are escaped as
+/// `\t`, `\n`, `\r` and `\\`
+pub fn tsv_row<S: AsRef<str>>(values: &[S]) -> String {
+    let values: Vec<String> = values.iter()
+        .map(|v| {
+            v.as_ref()
+                .replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r")
+        })
+        .collect();
+    values.join("\t")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Value as FieldValue;
+
+    fn record(fields: &[(u8, &[u8])]) -> Vec<Field> {
+        fields.iter().map(|&(typ, val)| Field::from(typ, FieldValue::from(val.to_vec()))).collect()
+    }
+
+    #[test]
+    fn test_record_json() {
+        let rec = record(&[(0x01, &[0x43; 16]), (0x03, b"git{hub}\n"), (0x06, b"secret"),
+                           (0x08, &[0xc0, 0xb9, 0x36, 0x5e]), (0x15, &[1]), (0x11, &[90, 0]),
+                           (0x1c, b"4111111111111111"), (0x0e, b"\\u")]);
+        let obj = record_json(&rec, false);
+        assert_eq!(obj["title"], "git{hub}\n");
+        assert_eq!(obj["ptime"], "2020-02-02T12:00:00Z");
+        assert_eq!(obj["protected"], true);
+        assert_eq!(obj["expiry_interval"], 90);
+        assert!(obj.get("password").is_none());
+        assert!(obj.get("cc_number").is_none());
+        assert_eq!(obj.as_object().unwrap().len(), 6);
+
+        let obj = record_json(&rec, true);
+        assert_eq!(obj["password"], "secret");
+        assert_eq!(obj["cc_number"], "4111111111111111");
+
+        assert_eq!(record_row(&rec, &["title", "protected", "email"], false),
+                   vec!["git{hub}\n", "true", ""]);
+    }
+
+    #[test]
+    fn test_columns() {
+        let columns = columns(false);
+        assert_eq!(&columns[..4], &["id", "uuid", "group", "title"]);
+        assert!(!columns.contains(&"password"));
+        assert!(self::columns(true).contains(&"password"));
+    }
+
+    #[test]
+    fn test_rows() {
+        assert_eq!(csv_row(&["a", "b,c", "say \"hi\"", "x\ny"]), "a,\"b,c\",\"say \"\"hi\"\"\",\"x\ny\"");
+        assert_eq!(tsv_row(&["a\tb", "x\ny", "c:\\"]), "a\\tb\tx\\ny\tc:\\\\");
+    }
+}
@@ -0,0 +1,320 @@
+//! Query expressions, e.g. for `pwx list --where`
+//!
+//! A query is a list of field tests joined with `and`, `or` and `not`,
+//! with parentheses for grouping. `and` binds tighter than `or`.
+//!
+//! ```text
+//! group~"work" and not url:""
+//! (title=~"^git" or email~example.com) and ptime < -90d
+//! ```
+//!
+//! Tests are `field op value`, or just `field` to test that the field is
+//! present (or set, for `protected`). Values are words or double quoted
+//! strings, with `\"` and `\\` escapes.
+//!
+//! - text fields: `group`, `title`, `username` (`user`), `notes`,
+//!   `password`, `url`, `email`, `command`, `autotype` and `grouptitle`
+//!   (group and title joined with a dot)
+//! - date fields: `ctime`, `ptime`, `atime`, `mtime` and `expiry` (the
+//!   password expiry, explicit or from the expiry interval)
+//!
+//! Text operators are `:` for an exact (case sensitive) match, `~` for a
+//! case insensitive substring and `=~` for a regular expression. Date
+//! operators are `<`, `<=`, `>`, `>=` and `:` (the same day), with day
+//! granularity. Dates are `YYYY-MM-DD`, `today`, or days relative to
+//! today, e.g. `-90d`. An empty value, e.g. `url:""`, matches records
+//! without the field.
+
+use super::Fail;
+use super::filter::{Expr, Selector, Test, TextOp, CmpOp, DateBound, Pattern};
+use super::chrono::naive::NaiveDate;
+use super::regex::Regex;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Op(&'static str),
+    Word(String),
+    Str(String),
+}
+
+/// Operators, longest first
+const OPS: &[&str] = &["=~", "<=", ">=", "<", ">", ":", "~", "="];
+
+fn invalid(msg: String) -> Fail {
+    Fail::InvalidQuery(msg)
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Fail> {
+    let mut tokens = Vec::new();
+    let mut rest = s;
+    loop {
+        rest = rest.trim_start();
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => break,
+        };
+
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::LParen } else { Token::RParen });
+            rest = &rest[1..];
+        } else if c == '"' {
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i + 2,
+                    Some((_, '\\')) => {
+                        match chars.next() {
+                            Some((_, c)) => value.push(c),
+                            None => return Err(invalid("unterminated string".to_owned())),
+                        }
+                    }
+                    Some((_, c)) => value.push(c),
+                    None => return Err(invalid("unterminated string".to_owned())),
+                }
+            };
+            tokens.push(Token::Str(value));
+            rest = &rest[end..];
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let end = rest.find(|c: char| {
+                    c.is_whitespace() || "()\"".contains(c) || OPS.iter().any(|op| op.starts_with(c))
+                })
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_owned()));
+            rest = &rest[end..];
+        }
+    }
+    Ok(tokens)
+}
+
+/// Map a field name to a selector
+fn selector(name: &str) -> Option<Selector> {
+    let sel = match name {
+        "group" => Selector::Text(0x02),
+        "title" => Selector::Text(0x03),
+        "username" | "user" => Selector::Text(0x04),
+        "notes" => Selector::Text(0x05),
+        "password" => Selector::Text(0x06),
+        "url" => Selector::Text(0x0d),
+        "autotype" => Selector::Text(0x0e),
+        "command" => Selector::Text(0x12),
+        "email" => Selector::Text(0x14),
+        "grouptitle" => Selector::GroupTitle,
+        "ctime" => Selector::Time(0x07),
+        "ptime" => Selector::Time(0x08),
+        "atime" => Selector::Time(0x09),
+        "mtime" => Selector::Time(0x0c),
+        "expiry" => Selector::Expiry,
+        "protected" => Selector::Flag(0x15),
+        _ => return None,
+    };
+    Some(sel)
+}
+
+/// Parse a date value, see the module documentation
+fn date(value: &str) -> Result<DateBound, Fail> {
+    if value == "today" {
+        return Ok(DateBound::Relative(0));
+    }
+    if let Some(days) = value.strip_suffix('d') {
+        if let Ok(days) = days.parse::<i64>() {
+            return Ok(DateBound::Relative(days));
+        }
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(DateBound::Absolute)
+        .map_err(|_| invalid(format!("not a date: {}", value)))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Consume a keyword, case insensitive
+    fn keyword(&mut self, kw: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(kw) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, Fail> {
+        let mut exprs = vec![self.and()?];
+        while self.keyword("or") {
+            exprs.push(self.and()?);
+        }
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { Expr::Or(exprs) })
+    }
+
+    fn and(&mut self) -> Result<Expr, Fail> {
+        let mut exprs = vec![self.unary()?];
+        while self.keyword("and") {
+            exprs.push(self.unary()?);
+        }
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { Expr::And(exprs) })
+    }
+
+    fn unary(&mut self) -> Result<Expr, Fail> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(invalid("missing )".to_owned())),
+                }
+            }
+            Some(Token::Word(name)) => self.test(&name),
+            Some(token) => Err(invalid(format!("unexpected {:?}", token))),
+            None => Err(invalid("unexpected end of query".to_owned())),
+        }
+    }
+
+    fn test(&mut self, name: &str) -> Result<Expr, Fail> {
+        let sel = selector(name).ok_or_else(|| invalid(format!("unknown field {}", name)))?;
+        let op = match self.peek() {
+            Some(&Token::Op(op)) => op,
+            _ => {
+                let test = match sel {
+                    Selector::Flag(_) => Test::Set,
+                    _ => Test::Present,
+                };
+                return Ok(Expr::Test(sel, test));
+            }
+        };
+        self.pos += 1;
+        let value = match self.next() {
+            Some(Token::Word(value)) | Some(Token::Str(value)) => value,
+            _ => return Err(invalid(format!("missing value after {}{}", name, op))),
+        };
+
+        if value.is_empty() && (op == ":" || op == "=") {
+            return Ok(Expr::Test(sel, Test::NotPresent));
+        }
+        let test = match (sel, op) {
+            (Selector::Text(_), ":") | (Selector::GroupTitle, ":") => {
+                Test::Text { op: TextOp::Equals, value, case: true }
+            }
+            (Selector::Text(_), "~") | (Selector::GroupTitle, "~") => {
+                Test::Text { op: TextOp::Contains, value, case: false }
+            }
+            (Selector::Text(_), "=~") | (Selector::GroupTitle, "=~") => {
+                let re = Regex::new(&value).map_err(|err| invalid(err.to_string()))?;
+                Test::Regex(Pattern(re))
+            }
+            (Selector::Time(_), _) | (Selector::Expiry, _) => {
+                let op = match op {
+                    ":" | "=" => CmpOp::Equals,
+                    "<" => CmpOp::Less,
+                    "<=" => CmpOp::LessEqual,
+                    ">" => CmpOp::Greater,
+                    ">=" => CmpOp::GreaterEqual,
+                    _ => return Err(invalid(format!("{} cannot be used with {}", op, name))),
+                };
+                let a = date(&value)?;
+                Test::Date { op, a, b: a }
+            }
+            _ => return Err(invalid(format!("{} cannot be used with {}", op, name))),
+        };
+        Ok(Expr::Test(sel, test))
+    }
+}
+
+/// Parse a query
+pub fn parse(query: &str) -> Result<Expr, Fail> {
+    let mut parser = Parser { tokens: tokenize(query)?, pos: 0 };
+    let expr = parser.or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(invalid(format!("unexpected {:?}", token))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::{Field, Value};
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn record(fields: &[(u8, &[u8])]) -> Vec<Field> {
+        fields.iter().map(|&(typ, val)| Field::from(typ, Value::from(val.to_vec()))).collect()
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("group~\"wo\\\"rk\" and(x<=-9d)").unwrap(),
+                   vec![Token::Word("group".to_owned()),
+                        Token::Op("~"),
+                        Token::Str("wo\"rk".to_owned()),
+                        Token::Word("and".to_owned()),
+                        Token::LParen,
+                        Token::Word("x".to_owned()),
+                        Token::Op("<="),
+                        Token::Word("-9d".to_owned()),
+                        Token::RParen]);
+        assert!(tokenize("title:\"open").is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let expr = parse("group~\"work\" and not url:\"\"").unwrap();
+        assert_eq!(expr,
+                   Expr::And(vec![Expr::Test(Selector::Text(0x02),
+                                             Test::Text { op: TextOp::Contains, value: "work".to_owned(), case: false }),
+                                  Expr::Not(Box::new(Expr::Test(Selector::Text(0x0d), Test::NotPresent)))]));
+        assert_eq!(parse("a:1 or b:2 and c:3").unwrap_err().to_string(), "Invalid query: unknown field a");
+        assert_eq!(parse("protected").unwrap(), Expr::Test(Selector::Flag(0x15), Test::Set));
+
+        for query in &["", "title:", "(title", "title:x)", "title<x", "ctime~2020", "ctime<soon",
+                       "title=~\"(\"", "title:x title:y"] {
+            assert!(parse(query).is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let work = record(&[(0x02, b"Work.git"), (0x03, b"github"), (0x0d, b"https://github.com"),
+                            (0x08, &[0xc0, 0xb9, 0x36, 0x5e])]);
+        let home = record(&[(0x02, b"Home"), (0x03, b"GitLab"), (0x15, &[1])]);
+        let now = now();
+        let check = |query: &str, rec: &[Field]| parse(query).unwrap().matches(rec, &now);
+
+        assert!(check("group~\"work\" and not url:\"\"", &work));
+        assert!(!check("group~\"work\" and not url:\"\"", &home));
+        assert!(check("title:github", &work));
+        assert!(!check("title:GitHub", &work));
+        assert!(check("title=~\"^[Gg]it\" and (protected or url)", &home));
+        assert!(check("grouptitle:Work.git.github", &work));
+        assert!(check("NOT group:Home OR title~lab", &home));
+        assert!(check("ptime < 2020-03-01 and ptime >= -120d", &work));
+        assert!(!check("ptime < -120d", &work));
+        assert!(check("ptime:2020-02-02", &work));
+        assert!(check("not expiry and expiry:\"\"", &work));
+    }
+}
@@ -0,0 +1,112 @@
+//! Fuzzy ranking of records, e.g. for `pwx list --rank`
+//!
+//! A keyword matches a field if its characters appear in order, not
+//! necessarily next to each other (like fzf). Consecutive characters and
+//! matches at the start of a word score higher, gaps score lower. The
+//! score of a record is the sum over the keywords of the best weighted
+//! field score, title over URL over username over group and notes.
+
+use super::db::Field;
+use super::record;
+use super::util::fold_case;
+
+/// Field types and their weights
+const WEIGHTS: &[(u8, i64)] = &[(0x03, 8), (0x0d, 4), (0x04, 3), (0x02, 2), (0x05, 1)];
+
+const SCORE_MATCH: i64 = 16;
+const BONUS_CONSECUTIVE: i64 = 8;
+const BONUS_WORD_START: i64 = 8;
+const BONUS_FIRST: i64 = 8;
+const PENALTY_GAP_START: i64 = 3;
+const PENALTY_GAP: i64 = 1;
+
+/// Score the match of the alignment starting at `start`, the rest of the
+/// needle is matched greedily
+fn score_from(needle: &[char], hay: &[char], start: usize) -> Option<i64> {
+    let mut score = 0;
+    let mut prev: Option<usize> = None;
+    let mut pos = start;
+
+    for &c in needle {
+        let idx = pos + hay[pos..].iter().position(|h| *h == c)?;
+        score += SCORE_MATCH;
+        if idx == 0 {
+            score += BONUS_FIRST;
+        }
+        if idx == 0 || !hay[idx - 1].is_alphanumeric() {
+            score += BONUS_WORD_START;
+        }
+        match prev {
+            Some(p) if p + 1 == idx => score += BONUS_CONSECUTIVE,
+            Some(p) => score -= PENALTY_GAP_START + PENALTY_GAP * (idx - p - 1) as i64,
+            None => (),
+        }
+        prev = Some(idx);
+        pos = idx + 1;
+    }
+    Some(score)
+}
+
+/// The fuzzy match score of a needle in a haystack, None if the needle is
+/// not a subsequence of the haystack. Matching is case insensitive, and
+/// optionally ignores accents, see `util::fold_case()`.
+pub fn score(needle: &str, hay: &str, ignore_accents: bool) -> Option<u32> {
+    let needle: Vec<char> = fold_case(needle, ignore_accents).chars().collect();
+    let hay: Vec<char> = fold_case(hay, ignore_accents).chars().collect();
+    let first = *needle.first()?;
+
+    (0..hay.len())
+        .filter(|&i| hay[i] == first)
+        .filter_map(|i| score_from(&needle, &hay, i))
+        .max()
+        .map(|score| score.max(1) as u32)
+}
+
+/// The score of a record for a list of keywords, None if any keyword
+/// does not match. Without keywords every record scores 0.
+pub fn score_record(rec: &[Field], keywords: &[String], ignore_accents: bool) -> Option<u32> {
+    let mut total = 0;
+    for keyword in keywords {
+        let best = WEIGHTS.iter()
+            .filter_map(|&(typ, weight)| {
+                let field = record::find(rec, typ)?;
+                let text = String::from_utf8_lossy(field.value().as_ref());
+                score(keyword, &text, ignore_accents).map(|s| s * weight as u32)
+            })
+            .max()?;
+        total += best;
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Value;
+
+    fn record(fields: &[(u8, &str)]) -> Vec<Field> {
+        fields.iter().map(|&(typ, val)| Field::from(typ, Value::from(val.as_bytes().to_vec()))).collect()
+    }
+
+    #[test]
+    fn test_score() {
+        assert_eq!(score("gh", "facebook", false), None);
+        assert_eq!(score("", "github", false), None);
+        assert!(score("gh", "GitHub", false).is_some());
+        assert!(score("gh", "gh-pages", false) > score("gh", "github", false));
+        assert!(score("hub", "github", false) > score("hub", "human resources b", false));
+        assert!(score("gl", "GitLab", false) > score("gl", "the gallery", false));
+        assert!(score("mul", "Müller", false).is_none());
+        assert!(score("mul", "Müller", true).is_some());
+    }
+
+    #[test]
+    fn test_score_record() {
+        let github = record(&[(0x03, "github"), (0x0d, "https://github.com")]);
+        let notes = record(&[(0x03, "mail"), (0x05, "see github")]);
+        let keywords = vec!["gh".to_owned()];
+        assert!(score_record(&github, &keywords, false) > score_record(&notes, &keywords, false));
+        assert_eq!(score_record(&github, &["gh".to_owned(), "zz".to_owned()], false), None);
+        assert_eq!(score_record(&notes, &[], false), Some(0));
+    }
+}
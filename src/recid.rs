@@ -0,0 +1,172 @@
+//! Record ids
+//!
+//! A record can be addressed by
+//!
+//! - its UUID, in base58 or hex (with or without hyphens)
+//! - a unique prefix of the base58 or hex UUID, at least 4 characters
+//! - its path `group/title` or `group/title[username]`, records without
+//!   a group are `title` or `title[username]`
+//! - a line of `pwx list` output, i.e. a full id followed by a space
+//!
+//! Full ids are tried first, then paths, then prefixes.
+
+use super::Fail;
+use super::db::Field;
+use super::record;
+use super::uuid::Uuid;
+use super::rust_base58::{ToBase58, FromBase58};
+
+/// The minimum length of an id prefix
+pub const MIN_PREFIX: usize = 4;
+
+/// Format an UUID as base58
+pub fn base58(uuid: &Uuid) -> String {
+    uuid.as_bytes().to_base58()
+}
+
+/// Parse a full record id, base58 or hex
+pub fn parse(recid: &str) -> Option<Uuid> {
+    match recid.from_base58() {
+        Ok(ref bin) if bin.len() == 16 => Uuid::from_bytes(bin).ok(),
+        _ => Uuid::parse_str(recid).ok(),
+    }
+}
+
+/// A short description of a record, e.g. for a list of candidates
+pub fn describe(rec: &[Field]) -> String {
+    let id = record::uuid(rec).map(|u| base58(&u)).unwrap_or_default();
+    format!("{} {}", id, path(rec))
+}
+
+fn text(rec: &[Field], typ: u8) -> String {
+    record::find(rec, typ)
+        .map(|f| String::from_utf8_lossy(f.value().as_ref()).into_owned())
+        .unwrap_or_default()
+}
+
+/// The record path, `group/title[username]`
+pub fn path(rec: &[Field]) -> String {
+    let group = text(rec, 0x02);
+    let title = text(rec, 0x03);
+    let username = text(rec, 0x04);
+    let mut path = if group.is_empty() {
+        title
+    } else {
+        format!("{}/{}", group, title)
+    };
+    if !username.is_empty() {
+        path.push_str(&format!("[{}]", username));
+    }
+    path
+}
+
+fn matches_path(rec: &[Field], spec: &str) -> bool {
+    let group = text(rec, 0x02);
+    let title = text(rec, 0x03);
+    let username = text(rec, 0x04);
+    if title.is_empty() {
+        return false;
+    }
+
+    let name = if group.is_empty() {
+        spec.strip_prefix('/').unwrap_or(spec)
+    } else {
+        match spec.strip_prefix(group.as_str()).and_then(|s| s.strip_prefix('/')) {
+            Some(name) => name,
+            None => return false,
+        }
+    };
+    match name.strip_prefix(title.as_str()) {
+        Some("") => true,
+        Some(rest) => rest == format!("[{}]", username),
+        None => false,
+    }
+}
+
+fn matches_prefix(uuid: &Uuid, spec: &str) -> bool {
+    if base58(uuid).starts_with(spec) {
+        return true;
+    }
+    let hex: String = spec.chars().filter(|c| *c != '-').collect::<String>().to_lowercase();
+    hex.chars().all(|c| c.is_ascii_hexdigit()) && uuid.simple().to_string().starts_with(&hex)
+}
+
+/// Find the index of the record with the given id or path. Fails with
+/// `Fail::UnknownRecord`, or `Fail::AmbiguousRecord` if more than one
+/// record matches.
+pub fn resolve(records: &[Vec<Field>], spec: &str) -> Result<usize, Fail> {
+    // A line of `pwx list` output starts with the id
+    let first = spec.split_whitespace().next().unwrap_or("");
+    for id in &[spec, first] {
+        if let Some(uuid) = parse(id) {
+            if let Some(idx) = records.iter().position(|rec| record::uuid(rec) == Some(uuid)) {
+                return Ok(idx);
+            }
+        }
+    }
+
+    let mut found: Vec<usize> = (0..records.len())
+        .filter(|&idx| matches_path(&records[idx], spec))
+        .collect();
+    if found.is_empty() && spec.chars().count() >= MIN_PREFIX {
+        found = (0..records.len())
+            .filter(|&idx| record::uuid(&records[idx]).map(|u| matches_prefix(&u, spec)).unwrap_or(false))
+            .collect();
+    }
+
+    match found.len() {
+        0 => Err(Fail::UnknownRecord(spec.to_owned())),
+        1 => Ok(found[0]),
+        _ => {
+            let candidates = found.iter().map(|&idx| describe(&records[idx])).collect();
+            Err(Fail::AmbiguousRecord(spec.to_owned(), candidates))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Value;
+
+    fn rec(uuid: &str, group: &str, title: &str, username: &str) -> Vec<Field> {
+        let uuid = Uuid::parse_str(uuid).unwrap();
+        let mut rec = vec![Field::from(0x01, Value::from(uuid.as_bytes().to_vec()))];
+        for &(typ, val) in &[(0x02, group), (0x03, title), (0x04, username)] {
+            if !val.is_empty() {
+                rec.push(Field::from(typ, Value::from(val.as_bytes().to_vec())));
+            }
+        }
+        rec
+    }
+
+    #[test]
+    fn test_resolve() {
+        let records = vec![rec("43fe1d0e-b65f-4e48-9abf-a1c5a1beeee8", "Work.git", "github", "devy"),
+                           rec("43fe1d0e-0000-4e48-9abf-a1c5a1beeee8", "Work.git", "github", "other"),
+                           rec("63a19136-46d9-4f75-827b-5312574233e8", "", "mail", "")];
+        let id = base58(&record::uuid(&records[2]).unwrap());
+
+        assert_eq!(resolve(&records, &id).unwrap(), 2);
+        assert_eq!(resolve(&records, "63a19136-46d9-4f75-827b-5312574233e8").unwrap(), 2);
+        assert_eq!(resolve(&records, &id[..5]).unwrap(), 2);
+        assert_eq!(resolve(&records, "63A1-9136").unwrap(), 2);
+        assert_eq!(resolve(&records, "43fe1d0eb6").unwrap(), 0);
+        assert_eq!(resolve(&records, "Work.git/github[other]").unwrap(), 1);
+        assert_eq!(resolve(&records, "mail").unwrap(), 2);
+        assert_eq!(resolve(&records, "/mail").unwrap(), 2);
+        assert_eq!(resolve(&records, &format!("{} mail []", id)).unwrap(), 2);
+
+        match resolve(&records, "Work.git/github") {
+            Err(Fail::AmbiguousRecord(_, candidates)) => {
+                assert_eq!(candidates.len(), 2);
+                assert!(candidates[0].ends_with(" Work.git/github[devy]"));
+            }
+            other => panic!("expected an ambiguous match, got {:?}", other),
+        }
+        assert!(resolve(&records, "43fe").is_err());
+        assert!(resolve(&records, "63a").is_err());
+        assert!(resolve(&records, "Work/github").is_err());
+        assert!(resolve(&records, "nothing").is_err());
+    }
+}
@@ -0,0 +1,550 @@
+//! Helpers for records, i.e. the list of fields returned by `PwxRecordIter`
+//!
+//! Functions that modify a record fail with `Fail::ProtectedRecord` if
+//! the record is protected, use `set_protected()` to clear the flag first.
+
+use std::convert::TryFrom;
+use super::Fail;
+use super::db::{Field, Value};
+use super::util::{from_time_t, to_time_t, read_uint};
+use super::chrono::naive::NaiveDateTime;
+use super::chrono::{DateTime, Utc};
+use super::chrono::Duration;
+use super::uuid::Uuid;
+use super::rand::RngCore;
+use super::rand::rngs::OsRng;
+
+/// Find the first non empty field of the given type, absent and zero
+/// length fields are the same. [sec. 2.9.2]
+pub fn find(record: &[Field], typ: u8) -> Option<&Field> {
+    record.iter().find(|f| f.typ() == typ && !f.value().as_ref().is_empty())
+}
+
+/// A new record with a random (version 4) UUID, and the creation,
+/// password modification and last modification times set to now
+pub fn new(now: &DateTime<Utc>) -> Result<Vec<Field>, Fail> {
+    let mut uuid = [0u8; 16];
+    OsRng.fill_bytes(&mut uuid);
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+
+    let ts = Value::from(to_time_t(now)?);
+    Ok(vec![Field::Uuid(Value::from(uuid.to_vec())),
+            Field::CreationTime(ts.clone()),
+            Field::PasswordModificationTime(ts.clone()),
+            Field::LastModificationTime(ts)])
+}
+
+/// The record UUID
+pub fn uuid(record: &[Field]) -> Option<Uuid> {
+    find(record, 0x01).and_then(|f| Uuid::from_bytes(f.value().as_ref()).ok())
+}
+
+/// Returns true if the record is protected, i.e. it cannot be changed
+/// or deleted. Any non zero value is protected. [sec. 3.3 note 17]
+pub fn is_protected(record: &[Field]) -> bool {
+    find(record, 0x15).map(|f| f.value().as_ref().iter().any(|b| *b != 0)).unwrap_or(false)
+}
+
+/// Set or clear the protected entry flag, this is always allowed
+pub fn set_protected(record: &mut Vec<Field>, protected: bool) {
+    record.retain(|f| f.typ() != 0x15);
+    if protected {
+        record.push(Field::Protected(Value::from(vec![1])));
+    }
+}
+
+/// Fails if the record is protected
+pub fn check_protected(record: &[Field]) -> Result<(), Fail> {
+    if is_protected(record) {
+        Err(Fail::ProtectedRecord)
+    } else {
+        Ok(())
+    }
+}
+
+/// Replace the first field of the same type, or append the field
+pub fn set_field(record: &mut Vec<Field>, field: Field) -> Result<(), Fail> {
+    check_protected(record)?;
+    match record.iter().position(|f| f.typ() == field.typ()) {
+        Some(idx) => record[idx] = field,
+        None => record.push(field),
+    }
+    Ok(())
+}
+
+/// Remove all fields of the given type
+pub fn remove_field(record: &mut Vec<Field>, typ: u8) -> Result<(), Fail> {
+    check_protected(record)?;
+    record.retain(|f| f.typ() != typ);
+    Ok(())
+}
+
+/// Read a time field, a zero time is the same as an absent field
+pub fn time(record: &[Field], typ: u8) -> Option<DateTime<Utc>> {
+    find(record, typ)
+        .and_then(|f| from_time_t(f.value().as_ref()).ok())
+        .filter(|ts| ts.timestamp() != 0)
+}
+
+/// Like `time()`, but fails with `Fail::InvalidValue` if the field is not
+/// a valid time
+pub fn try_time(record: &[Field], typ: u8) -> Result<Option<DateTime<Utc>>, Fail> {
+    let field = match find(record, typ) {
+        Some(field) => field,
+        None => return Ok(None),
+    };
+    match from_time_t(field.value().as_ref()) {
+        Ok(ts) => Ok(Some(ts).filter(|ts| ts.timestamp() != 0)),
+        Err(Fail::InvalidValue(err)) => {
+            Err(Fail::InvalidValue(format!("{}: {}", field.name().unwrap_or("time"), err)))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// The password expiry interval in days (0x11), valid values are 1-3650
+pub fn expiry_interval(record: &[Field]) -> Option<i64> {
+    find(record, 0x11)
+        .and_then(|f| read_uint(f.value().as_ref()))
+        .map(|days| days as i64)
+        .filter(|days| *days >= 1 && *days <= 3650)
+}
+
+/// Returns the time the password expires. This is the password expiry
+/// time (0x0a) if set, otherwise the expiry interval (0x11) counts from
+/// the last password change, or from the record creation.
+pub fn expiry(record: &[Field]) -> Option<DateTime<Utc>> {
+    if let Some(ts) = time(record, 0x0a) {
+        return Some(ts);
+    }
+    let days = expiry_interval(record)?;
+    let changed = time(record, 0x08).or_else(|| time(record, 0x07))?;
+    Some(changed + Duration::days(days))
+}
+
+/// Password history (0x0f) [sec. 3.3 note 12]
+///
+/// The field is `fmmnn` followed by the entries `TLP`, where `f` is 1 if
+/// the history is kept, `mm` is the maximum and `nn` the number of
+/// entries. Each entry has the time the password was set `T`, its length
+/// `L` and the password `P`. All numbers are hex.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    pub active: bool,
+    pub max: usize,
+    /// The old passwords, oldest first
+    pub entries: Vec<(DateTime<Utc>, String)>,
+}
+
+impl History {
+    pub fn parse(val: &[u8]) -> Option<History> {
+        let chars: Vec<char> = ::std::str::from_utf8(val).ok()?.chars().collect();
+        let mut pos = 0;
+        let take = |pos: &mut usize, n: usize| -> Option<String> {
+            let s = chars.get(*pos..*pos + n)?.iter().collect();
+            *pos += n;
+            Some(s)
+        };
+        let hex = |pos: &mut usize, n| take(pos, n).and_then(|h| usize::from_str_radix(&h, 16).ok());
+
+        let active = match hex(&mut pos, 1)? {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        let max = hex(&mut pos, 2)?;
+        let count = hex(&mut pos, 2)?;
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            let ts = from_time_t(&(hex(&mut pos, 8)? as u32).to_le_bytes()).ok()?;
+            let len = hex(&mut pos, 4)?;
+            entries.push((ts, take(&mut pos, len)?));
+        }
+        Some(History { active, max, entries })
+    }
+
+    /// The field value. The times are 8 hex digits, times before 1970
+    /// or after 2106 are an error.
+    pub fn to_value(&self) -> Result<Value, Fail> {
+        let mut s = format!("{}{:02x}{:02x}", self.active as u8, self.max, self.entries.len());
+        for (ts, password) in &self.entries {
+            let secs = u32::try_from(ts.timestamp())
+                .map_err(|_| Fail::InvalidValue(format!("password history time out of range {}", ts)))?;
+            s.push_str(&format!("{:08x}{:04x}{}", secs, password.chars().count(), password));
+        }
+        Ok(Value::from(s.into_bytes()))
+    }
+}
+
+/// The password history of the record, if any
+pub fn history(record: &[Field]) -> Option<History> {
+    find(record, 0x0f).and_then(|f| History::parse(f.value().as_ref()))
+}
+
+/// Change the record password. This updates the password modification
+/// time and, if the record has an expiry interval, the expiry time. If
+/// the password history is kept the old password is added to it.
+pub fn set_password(record: &mut Vec<Field>, password: &[u8], now: &DateTime<Utc>) -> Result<(), Fail> {
+    check_protected(record)?;
+    if let Some(mut history) = history(record).filter(|h| h.active && h.max > 0) {
+        if let Some(old) = find(record, 0x06) {
+            let set = time(record, 0x08).or_else(|| time(record, 0x07)).unwrap_or(*now);
+            let old = String::from_utf8_lossy(old.value().as_ref()).into_owned();
+            history.entries.push((set, old));
+            let extra = history.entries.len().saturating_sub(history.max);
+            history.entries.drain(..extra);
+            set_field(record, Field::from(0x0f, history.to_value()?))?;
+        }
+    }
+
+    set_field(record, Field::Password(Value::from(password.to_vec())))?;
+    set_field(record, Field::PasswordModificationTime(Value::from(to_time_t(now)?)))?;
+    if let Some(days) = expiry_interval(record) {
+        let expires = *now + Duration::days(days);
+        set_field(record, Field::PasswordExpiryTime(Value::from(to_time_t(&expires)?)))?;
+    }
+    Ok(())
+}
+
+/// Set the last modification time (0x0c)
+pub fn touch(record: &mut Vec<Field>, now: &DateTime<Utc>) -> Result<(), Fail> {
+    set_field(record, Field::LastModificationTime(Value::from(to_time_t(now)?)))
+}
+
+/// Split a group path into its elements. Groups are separated by dots,
+/// a dot inside a group name is escaped as `\.` [sec. 3.3 note 2]
+pub fn split_group(group: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = group.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                match chars.next() {
+                    Some('.') => parts.last_mut().unwrap().push('.'),
+                    Some(c) => {
+                        parts.last_mut().unwrap().push('\\');
+                        parts.last_mut().unwrap().push(c);
+                    }
+                    None => parts.last_mut().unwrap().push('\\'),
+                }
+            }
+            '.' => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts.retain(|p| !p.is_empty());
+    parts
+}
+
+/// Join group elements into a path, the reverse of `split_group()`
+pub fn join_group<S: AsRef<str>>(parts: &[S]) -> String {
+    parts.iter().map(|p| p.as_ref().replace('.', "\\.")).collect::<Vec<_>>().join(".")
+}
+
+/// Set the group of a record, an empty group removes the field
+pub fn set_group(record: &mut Vec<Field>, group: &str) -> Result<(), Fail> {
+    if group.is_empty() {
+        remove_field(record, 0x02)
+    } else {
+        set_field(record, Field::Group(Value::from(group.as_bytes().to_vec())))
+    }
+}
+
+/// Move a record in the group `old`, or any of its subgroups, to `new`.
+/// Both are group paths, returns true if the record was moved.
+pub fn rename_group(record: &mut Vec<Field>, old: &str, new: &str) -> Result<bool, Fail> {
+    let old = split_group(old);
+    let group = find(record, 0x02)
+        .map(|f| split_group(&String::from_utf8_lossy(f.value().as_ref())))
+        .unwrap_or_default();
+    if old.is_empty() || !group.starts_with(&old) {
+        return Ok(false);
+    }
+    let mut moved = split_group(new);
+    moved.extend_from_slice(&group[old.len()..]);
+    set_group(record, &join_group(&moved))?;
+    Ok(true)
+}
+
+/// Aliases and shortcuts refer to a base entry through their password
+/// field, `[[uuid]]` for an alias and `[~uuid~]` for a shortcut, with
+/// the UUID as 32 hex digits [sec. 3.3 notes 3, 4]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Link {
+    /// Uses the password of the base entry
+    Alias(Uuid),
+    /// Uses all fields of the base entry, except group, title and username
+    Shortcut(Uuid),
+}
+
+impl Link {
+    pub fn base(&self) -> Uuid {
+        match *self {
+            Link::Alias(uuid) | Link::Shortcut(uuid) => uuid,
+        }
+    }
+}
+
+/// The base entry of an alias or shortcut
+pub fn link(record: &[Field]) -> Option<Link> {
+    let password = find(record, 0x06)?.value().as_ref();
+    let password = ::std::str::from_utf8(password).ok()?;
+    let parse = |hex: &str| {
+        if hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            Uuid::parse_str(hex).ok()
+        } else {
+            None
+        }
+    };
+    if let Some(hex) = password.strip_prefix("[[").and_then(|p| p.strip_suffix("]]")) {
+        parse(hex).map(Link::Alias)
+    } else if let Some(hex) = password.strip_prefix("[~").and_then(|p| p.strip_suffix("~]")) {
+        parse(hex).map(Link::Shortcut)
+    } else {
+        None
+    }
+}
+
+/// Convert an alias or shortcut into a normal entry, by copying the
+/// password from the base entry. Shortcuts also get the base fields
+/// they don't have.
+pub fn unlink(record: &mut Vec<Field>, base: &[Field]) -> Result<(), Fail> {
+    check_protected(record)?;
+    if let Some(Link::Shortcut(_)) = link(record) {
+        for field in base {
+            if field.typ() != 0x01 && field.typ() != 0x15 && find(record, field.typ()).is_none() {
+                record.push(field.clone());
+            }
+        }
+    }
+    let password = find(base, 0x06).map(|f| f.value().as_ref().to_vec()).unwrap_or_default();
+    set_field(record, Field::Password(Value::from(password)))
+}
+
+/// Time format used by `to_text()` and `from_text()`
+const TIME_FMT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Returns true if the field type can be edited as text, see
+/// `to_text()` and `from_text()`
+pub fn is_editable(typ: u8) -> bool {
+    matches!(typ, 0x02..=0x0a | 0x0c | 0x0d | 0x11 | 0x12 | 0x14 | 0x1b..=0x1f)
+}
+
+/// The field value as editable text, text fields are not masked
+pub fn to_text(field: &Field) -> Option<String> {
+    let val = field.value().as_ref();
+    match field.typ() {
+        0x07..=0x0a | 0x0c => from_time_t(val).ok().map(|ts| ts.format(TIME_FMT).to_string()),
+        0x11 => read_uint(val).map(|days| days.to_string()),
+        typ if is_editable(typ) => Some(String::from_utf8_lossy(val).into_owned()),
+        _ => None,
+    }
+}
+
+/// Parse the text for a field value, times use the format
+/// `YYYY-MM-DD HH:MM:SS` (UTC) and the expiry interval is in days
+pub fn from_text(typ: u8, text: &str) -> Result<Field, Fail> {
+    let val = match typ {
+        0x07..=0x0a | 0x0c => {
+            NaiveDateTime::parse_from_str(text.trim(), TIME_FMT)
+                .map_err(|_| Fail::InvalidValue(format!("invalid time {}", text)))
+                .and_then(|ts| to_time_t(&ts.and_utc()))?
+        }
+        0x11 => {
+            text.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|days| (1..=3650).contains(days))
+                .map(|days| days.to_le_bytes().to_vec())
+                .ok_or_else(|| Fail::InvalidValue(format!("invalid expiry interval {}", text)))?
+        }
+        typ if is_editable(typ) => text.as_bytes().to_vec(),
+        _ => return Err(Fail::InvalidValue(format!("field {} cannot be edited", typ))),
+    };
+    Ok(Field::from(typ, Value::from(val)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn ts(y: i32, m: u32, d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap()
+    }
+
+    fn field(typ: u8, val: Vec<u8>) -> Field {
+        Field::from(typ, Value::from(val))
+    }
+
+    #[test]
+    fn test_expiry() {
+        assert_eq!(expiry(&[]), None);
+
+        let ctime = field(0x07, to_time_t(&ts(2017, 1, 1)).unwrap());
+        let ptime = field(0x08, to_time_t(&ts(2017, 3, 1)).unwrap());
+        let xtime = field(0x0a, to_time_t(&ts(2017, 2, 1)).unwrap());
+        let interval = field(0x11, vec![10, 0]);
+
+        // Explicit expiry time
+        assert_eq!(expiry(&[ctime.clone(), xtime.clone(), interval.clone()]), Some(ts(2017, 2, 1)));
+        // A zero expiry time never expires
+        assert_eq!(expiry(&[ctime.clone(), field(0x0a, vec![0, 0, 0, 0])]), None);
+        // Interval from password change or creation
+        assert_eq!(expiry(&[ctime.clone(), ptime.clone(), interval.clone()]), Some(ts(2017, 3, 11)));
+        assert_eq!(expiry(&[ctime.clone(), interval.clone()]), Some(ts(2017, 1, 11)));
+        assert_eq!(expiry(std::slice::from_ref(&interval)), None);
+        // Out of range intervals are ignored
+        assert_eq!(expiry(&[ctime.clone(), field(0x11, vec![0, 0])]), None);
+        assert_eq!(expiry(&[ctime.clone(), field(0x11, vec![0x43, 0x0e])]), None);
+    }
+
+    #[test]
+    fn test_try_time() {
+        let ctime = field(0x07, to_time_t(&ts(2017, 1, 1)).unwrap());
+        assert_eq!(try_time(std::slice::from_ref(&ctime), 0x07).unwrap(), Some(ts(2017, 1, 1)));
+        assert_eq!(try_time(&[ctime], 0x08).unwrap(), None);
+        assert_eq!(try_time(&[field(0x07, vec![0; 4])], 0x07).unwrap(), None);
+        let err = try_time(&[field(0x07, vec![1, 2, 3])], 0x07).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid value: ctime: time_t of 3 bytes");
+        let err = try_time(&[field(0x08, vec![0xff; 8])], 0x08).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid value: ptime: negative time_t -1");
+    }
+
+    #[test]
+    fn test_set_password() {
+        let mut rec = vec![field(0x06, b"old".to_vec()),
+                           field(0x07, to_time_t(&ts(2017, 1, 1)).unwrap()),
+                           field(0x0a, to_time_t(&ts(2017, 2, 1)).unwrap()),
+                           field(0x11, vec![90, 0, 0, 0])];
+        set_password(&mut rec, b"new", &ts(2018, 1, 1)).unwrap();
+        assert!(find(&rec, 0x06) == Some(&field(0x06, b"new".to_vec())));
+        assert_eq!(time(&rec, 0x08), Some(ts(2018, 1, 1)));
+        assert_eq!(expiry(&rec), Some(ts(2018, 4, 1)));
+
+        // Without an interval the expiry time is kept
+        let mut rec = vec![field(0x0a, to_time_t(&ts(2017, 2, 1)).unwrap())];
+        set_password(&mut rec, b"new", &ts(2018, 1, 1)).unwrap();
+        assert_eq!(expiry(&rec), Some(ts(2017, 2, 1)));
+    }
+
+    #[test]
+    fn test_history() {
+        assert_eq!(History::parse(b"00300"), Some(History { active: false, max: 3, entries: vec![] }));
+        assert_eq!(History::parse(b"1030"), None);
+        assert_eq!(History::parse(b"10301"), None);
+
+        // Times must fit in 8 hex digits
+        let mut h = History { active: true, max: 3, entries: vec![(ts(2106, 2, 7), "x".to_owned())] };
+        assert_eq!(History::parse(h.to_value().unwrap().as_ref()), Some(h.clone()));
+        h.entries[0].0 = ts(2106, 2, 8);
+        assert!(h.to_value().is_err());
+        h.entries[0].0 = ts(1969, 12, 31);
+        assert!(h.to_value().is_err());
+
+        let mut rec = vec![field(0x06, b"first".to_vec()),
+                           field(0x07, to_time_t(&ts(2017, 1, 1)).unwrap()),
+                           field(0x0f, b"10200".to_vec())];
+        set_password(&mut rec, b"second", &ts(2018, 1, 1)).unwrap();
+        set_password(&mut rec, b"third", &ts(2019, 1, 1)).unwrap();
+        set_password(&mut rec, "f\u{f6}urth".as_bytes(), &ts(2020, 1, 1)).unwrap();
+        assert_eq!(history(&rec).unwrap().entries,
+                   vec![(ts(2018, 1, 1), "second".to_owned()), (ts(2019, 1, 1), "third".to_owned())]);
+        assert_eq!(find(&rec, 0x0f).unwrap().value().as_ref(),
+                   &b"102025a497a000006second5c2aad800005third"[..]);
+
+        // Inactive history is not updated
+        let mut rec = vec![field(0x06, b"first".to_vec()), field(0x0f, b"00300".to_vec())];
+        set_password(&mut rec, b"second", &ts(2018, 1, 1)).unwrap();
+        assert_eq!(history(&rec).unwrap().entries, vec![]);
+    }
+
+    #[test]
+    fn test_text() {
+        let f = from_text(0x07, "2017-01-02 03:04:05").unwrap();
+        assert_eq!(to_text(&f), Some("2017-01-02 03:04:05".to_owned()));
+        assert!(from_text(0x07, "2017-01-02").is_err());
+        assert_eq!(from_text(0x07, "1969-12-31 00:00:00").err().map(|e| e.to_string()),
+                   Some("Invalid value: negative time_t -86400".to_owned()));
+        assert!(from_text(0x11, "0").is_err());
+        assert_eq!(to_text(&from_text(0x11, "90").unwrap()), Some("90".to_owned()));
+        assert_eq!(from_text(0x11, "90").unwrap().value().as_ref(), &[90, 0]);
+        assert!(from_text(0x11, "70000").is_err());
+        assert_eq!(to_text(&from_text(0x1c, "4111 1111").unwrap()), Some("4111 1111".to_owned()));
+        assert!(from_text(0x01, "x").is_err());
+        assert!(from_text(0x15, "1").is_err());
+        assert_eq!(Field::typ_from_name("cc_number"), Some(0x1c));
+        assert_eq!(Field::typ_from_name("nope"), None);
+    }
+
+    #[test]
+    fn test_new() {
+        let now = ts(2020, 1, 2);
+        let rec = new(&now).unwrap();
+        let uuid = uuid(&rec).unwrap();
+        assert_eq!(uuid.get_version_num(), 4);
+        assert!(uuid != super::uuid(&new(&now).unwrap()).unwrap());
+        for typ in &[0x07, 0x08, 0x0c] {
+            assert_eq!(time(&rec, *typ), Some(now));
+        }
+    }
+
+    #[test]
+    fn test_protected() {
+        let mut rec = vec![field(0x06, b"old".to_vec()), field(0x15, vec![0])];
+        assert!(!is_protected(&rec));
+
+        set_protected(&mut rec, true);
+        assert!(is_protected(&rec));
+        assert!(set_password(&mut rec, b"new", &ts(2018, 1, 1)).is_err());
+        assert!(set_field(&mut rec, field(0x03, b"title".to_vec())).is_err());
+        assert!(remove_field(&mut rec, 0x06).is_err());
+        assert!(rec == vec![field(0x06, b"old".to_vec()), field(0x15, vec![1])]);
+
+        set_protected(&mut rec, false);
+        assert!(!is_protected(&rec));
+        assert!(set_password(&mut rec, b"new", &ts(2018, 1, 1)).is_ok());
+    }
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(split_group("a.b\\.c..d"), vec!["a", "b.c", "d"]);
+        assert_eq!(join_group(&["a", "b.c"]), "a.b\\.c");
+        assert!(split_group("").is_empty());
+
+        let mut rec = vec![field(0x02, b"Work.old\\.name.sub".to_vec())];
+        assert!(!rename_group(&mut rec, "Work.old", "New").unwrap());
+        assert!(rename_group(&mut rec, "Work.old\\.name", "Home.new\\.name").unwrap());
+        assert_eq!(find(&rec, 0x02).unwrap().value().as_ref(), b"Home.new\\.name.sub");
+        assert!(rename_group(&mut rec, "Home", "").unwrap());
+        assert_eq!(find(&rec, 0x02).unwrap().value().as_ref(), b"new\\.name.sub");
+        assert!(rename_group(&mut rec, "new\\.name.sub", "").unwrap());
+        assert!(find(&rec, 0x02).is_none());
+    }
+
+    #[test]
+    fn test_link() {
+        let base = vec![field(0x01, vec![0x11; 16]),
+                        field(0x06, b"secret".to_vec()),
+                        field(0x0d, b"https://example.com".to_vec())];
+        let uuid = super::uuid(&base).unwrap();
+        let hex = "11111111111111111111111111111111";
+
+        let mut alias = vec![field(0x06, format!("[[{}]]", hex).into_bytes())];
+        assert_eq!(link(&alias), Some(Link::Alias(uuid)));
+        unlink(&mut alias, &base).unwrap();
+        assert!(alias == vec![field(0x06, b"secret".to_vec())]);
+        assert_eq!(link(&alias), None);
+
+        let mut shortcut = vec![field(0x03, b"short".to_vec()),
+                                field(0x06, format!("[~{}~]", hex).into_bytes())];
+        assert_eq!(link(&shortcut).map(|l| l.base()), Some(uuid));
+        unlink(&mut shortcut, &base).unwrap();
+        assert!(shortcut == vec![field(0x03, b"short".to_vec()),
+                                 field(0x06, b"secret".to_vec()),
+                                 field(0x0d, b"https://example.com".to_vec())]);
+
+        assert_eq!(link(&[field(0x06, b"[[not a uuid]]".to_vec())]), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Run Command (0x12) variable expansion
+//!
+//! PasswordSafe expands variables in the run command with the record
+//! fields, e.g. `ssh $u@$url`. The supported variables are
+//!
+//! - `$g`, `$group`
+//! - `$t`, `$title`
+//! - `$u`, `$user`, `$username`
+//! - `$p`, `$password`
+//! - `$n`, `$notes`
+//! - `$e`, `$email`
+//! - `$url`
+//! - `$a`, `$autotype`, `\b`
+//!
+//! Variables can also be written as `${name}`, other `$` sequences are
+//! left untouched.
+//!
+//! The command is run with `sh -c`, with the variables expanded as
+//! references to environment variables (e.g. `$PWX_RUN_PASSWORD`) instead
+//! of being pasted into the command line. This keeps the field values
+//! out of the shell's argv and avoids quoting issues.
+
+use super::db::Field;
+use super::record;
+use super::secstr::SecStr;
+
+/// A run command variable
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Var {
+    Group,
+    Title,
+    Username,
+    Password,
+    Notes,
+    Email,
+    Url,
+    Autotype,
+}
+
+impl Var {
+    fn from_name(name: &str) -> Option<Var> {
+        let var = match name {
+            "g" | "group" => Var::Group,
+            "t" | "title" => Var::Title,
+            "u" | "user" | "username" => Var::Username,
+            "p" | "password" => Var::Password,
+            "n" | "notes" => Var::Notes,
+            "e" | "email" => Var::Email,
+            "url" => Var::Url,
+            "a" | "autotype" => Var::Autotype,
+            _ => return None,
+        };
+        Some(var)
+    }
+
+    /// The record field type for this variable
+    pub fn typ(&self) -> u8 {
+        match *self {
+            Var::Group => 0x02,
+            Var::Title => 0x03,
+            Var::Username => 0x04,
+            Var::Notes => 0x05,
+            Var::Password => 0x06,
+            Var::Url => 0x0d,
+            Var::Autotype => 0x0e,
+            Var::Email => 0x14,
+        }
+    }
+
+    /// The environment variable used to pass the value to the command
+    pub fn env_name(&self) -> &'static str {
+        match *self {
+            Var::Group => "PWX_RUN_GROUP",
+            Var::Title => "PWX_RUN_TITLE",
+            Var::Username => "PWX_RUN_USERNAME",
+            Var::Password => "PWX_RUN_PASSWORD",
+            Var::Notes => "PWX_RUN_NOTES",
+            Var::Email => "PWX_RUN_EMAIL",
+            Var::Url => "PWX_RUN_URL",
+            Var::Autotype => "PWX_RUN_AUTOTYPE",
+        }
+    }
+
+    /// Secret values are masked when displaying the command. Only the
+    /// group, title, username, URL and email are shown, the notes and the
+    /// autotype field (which can contain `\p`) may hold secrets too.
+    pub fn is_secret(&self) -> bool {
+        match *self {
+            Var::Password | Var::Notes | Var::Autotype => true,
+            Var::Group | Var::Title | Var::Username | Var::Url | Var::Email => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part {
+    Text(String),
+    Var(Var),
+}
+
+/// A parsed run command
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunCommand {
+    pub parts: Vec<Part>,
+}
+
+/// Shell quoting state, to know how to insert a variable reference
+#[derive(Clone, Copy, PartialEq)]
+enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+impl RunCommand {
+    /// Split a run command into text and variables
+    pub fn parse(cmd: &str) -> RunCommand {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = cmd;
+
+        while let Some(c) = rest.chars().next() {
+            let (var, len) = if c == '$' {
+                let after = &rest[1..];
+                if after.starts_with('{') {
+                    match after.find('}') {
+                        Some(end) => (Var::from_name(&after[1..end]), end + 2),
+                        None => (None, 1),
+                    }
+                } else {
+                    let end = after.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(after.len());
+                    (Var::from_name(&after[..end]), end + 1)
+                }
+            } else if rest.starts_with("\\b") {
+                (Some(Var::Autotype), 2)
+            } else {
+                (None, c.len_utf8())
+            };
+
+            match var {
+                Some(var) => {
+                    if !text.is_empty() {
+                        parts.push(Part::Text(text.clone()));
+                        text.clear();
+                    }
+                    parts.push(Part::Var(var));
+                    rest = &rest[len..];
+                }
+                None => {
+                    text.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        RunCommand { parts }
+    }
+
+    /// The command for `sh -c`, each variable is replaced with a
+    /// reference to its environment variable, quoted according to
+    /// where it appears in the command.
+    pub fn sh_command(&self) -> String {
+        let mut out = String::new();
+        let mut quote = Quote::None;
+        let mut escaped = false;
+
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref s) => {
+                    for c in s.chars() {
+                        out.push(c);
+                        if escaped {
+                            escaped = false;
+                            continue;
+                        }
+                        quote = match (quote, c) {
+                            (Quote::None, '\'') => Quote::Single,
+                            (Quote::None, '"') => Quote::Double,
+                            (Quote::Single, '\'') | (Quote::Double, '"') => Quote::None,
+                            (Quote::None, '\\') | (Quote::Double, '\\') => {
+                                escaped = true;
+                                quote
+                            }
+                            _ => quote,
+                        };
+                    }
+                }
+                Part::Var(var) => {
+                    let name = var.env_name();
+                    match quote {
+                        Quote::None => out.push_str(&format!("\"${{{}}}\"", name)),
+                        Quote::Double => out.push_str(&format!("${{{}}}", name)),
+                        Quote::Single => out.push_str(&format!("'\"${{{}}}\"'", name)),
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// The environment for the command, the value of each variable
+    /// used in the command. Missing fields are empty.
+    pub fn env(&self, rec: &[Field]) -> Vec<(&'static str, SecStr)> {
+        let mut env: Vec<(&'static str, SecStr)> = Vec::new();
+        for part in &self.parts {
+            if let Part::Var(var) = *part {
+                if env.iter().any(|&(name, _)| name == var.env_name()) {
+                    continue;
+                }
+                let val = record::find(rec, var.typ())
+                    .map(|f| SecStr::from(f.value().as_ref()))
+                    .unwrap_or_else(|| SecStr::from(""));
+                env.push((var.env_name(), val));
+            }
+        }
+        env
+    }
+
+    /// The command with the variables expanded as text, secrets are
+    /// masked. This is only meant for display.
+    pub fn masked(&self, rec: &[Field]) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref s) => out.push_str(s),
+                Part::Var(var) if var.is_secret() => out.push_str("********"),
+                Part::Var(var) => {
+                    if let Some(f) = record::find(rec, var.typ()) {
+                        out.push_str(&String::from_utf8_lossy(f.value().as_ref()));
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Value;
+
+    fn field(typ: u8, val: &str) -> Field {
+        Field::from(typ, Value::from(val.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn test_parse() {
+        let cmd = RunCommand::parse("ssh $u@$url -p${p}x $HOME \\b $");
+        assert_eq!(cmd.parts,
+                   vec![Part::Text("ssh ".to_owned()),
+                        Part::Var(Var::Username),
+                        Part::Text("@".to_owned()),
+                        Part::Var(Var::Url),
+                        Part::Text(" -p".to_owned()),
+                        Part::Var(Var::Password),
+                        Part::Text("x $HOME ".to_owned()),
+                        Part::Var(Var::Autotype),
+                        Part::Text(" $".to_owned())]);
+        assert_eq!(RunCommand::parse("${nope} ${g").parts,
+                   vec![Part::Text("${nope} ${g".to_owned())]);
+    }
+
+    #[test]
+    fn test_sh_command() {
+        let cmd = RunCommand::parse(r#"login $u "$t \"x\" $p" '$g' \'$e"#);
+        assert_eq!(cmd.sh_command(),
+                   r#"login "${PWX_RUN_USERNAME}" "${PWX_RUN_TITLE} \"x\" ${PWX_RUN_PASSWORD}" ''"${PWX_RUN_GROUP}"'' \'"${PWX_RUN_EMAIL}""#);
+    }
+
+    #[test]
+    fn test_env_masked() {
+        let rec = vec![field(0x03, "github"), field(0x04, "devy"), field(0x06, "secret")];
+        let cmd = RunCommand::parse("x $u $p $password $url");
+        let env = cmd.env(&rec);
+        assert_eq!(env.len(), 3);
+        assert_eq!(env[0].0, "PWX_RUN_USERNAME");
+        assert_eq!(env[0].1.unsecure(), b"devy");
+        assert_eq!(env[1].1.unsecure(), b"secret");
+        assert_eq!(env[2].1.unsecure(), b"");
+        assert_eq!(cmd.masked(&rec), "x devy ******** ******** ");
+
+        let rec = vec![field(0x03, "github"), field(0x05, "pin 1234"), field(0x0e, "\\u\\t\\p\\n"),
+                       field(0x0d, "x.org"), field(0x14, "a@b"), field(0x02, "g")];
+        let cmd = RunCommand::parse("$g $t $n ${notes} $a \\b $url $e");
+        assert_eq!(cmd.masked(&rec), "g github ******** ******** ******** ******** x.org a@b");
+    }
+}
@@ -0,0 +1,114 @@
+//! Aligned text tables, e.g. for `pwx list` on a terminal
+//!
+//! Columns are as wide as their widest value. If the table is wider than
+//! the terminal, the widest columns are shrunk and their values truncated
+//! with an ellipsis.
+
+use super::unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Space between columns
+const SEPARATOR: &str = "  ";
+
+/// Columns are not shrunk below this width
+const MIN_WIDTH: usize = 6;
+
+/// Replace line breaks and tabs with spaces, so a value fits on one line
+fn one_line(s: &str) -> String {
+    s.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+/// Truncate a string to a display width, with an ellipsis if it is cut
+pub fn truncate(s: &str, width: usize) -> String {
+    if s.width() <= width {
+        return s.to_owned();
+    }
+    let mut out = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        out.push(c);
+        used += w;
+    }
+    if width > 0 {
+        out.push('…');
+    }
+    out
+}
+
+/// Pad a string with spaces to a display width
+fn pad(s: &str, width: usize) -> String {
+    let mut out = s.to_owned();
+    for _ in s.width()..width {
+        out.push(' ');
+    }
+    out
+}
+
+/// Render a table with a header line, at most `width` columns wide
+pub fn render(headers: &[String], rows: &[Vec<String>], width: usize) -> String {
+    let rows: Vec<Vec<String>> = rows.iter()
+        .map(|row| row.iter().map(|v| one_line(v)).collect())
+        .collect();
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.width()).collect();
+    for row in &rows {
+        for (w, val) in widths.iter_mut().zip(row) {
+            *w = (*w).max(val.width());
+        }
+    }
+
+    let sep = SEPARATOR.len() * headers.len().saturating_sub(1);
+    while widths.iter().sum::<usize>() + sep > width {
+        match widths.iter_mut().filter(|w| **w > MIN_WIDTH).max() {
+            Some(w) => *w -= 1,
+            None => break,
+        }
+    }
+
+    let mut out = String::new();
+    for row in ::std::iter::once(headers).chain(rows.iter().map(|r| r.as_slice())) {
+        let cells: Vec<String> = widths.iter()
+            .enumerate()
+            .map(|(i, &w)| pad(&truncate(row.get(i).map(|s| s.as_str()).unwrap_or(""), w), w))
+            .collect();
+        out.push_str(cells.join(SEPARATOR).trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("github", 6), "github");
+        assert_eq!(truncate("github", 5), "gith…");
+        assert_eq!(truncate("日本語の", 5), "日本…");
+        assert_eq!(truncate("abc", 0), "");
+    }
+
+    #[test]
+    fn test_render() {
+        let headers = strings(&["TITLE", "USERNAME"]);
+        let rows = vec![strings(&["github", "devy"]), strings(&["Müller\nGmbH", ""])];
+        assert_eq!(render(&headers, &rows, 80),
+                   "TITLE        USERNAME\n\
+                    github       devy\n\
+                    Müller GmbH\n");
+
+        let rows = vec![strings(&["a very long title that does not fit", "someone@example.com"])];
+        let table = render(&headers, &rows, 30);
+        assert_eq!(table,
+                   "TITLE           USERNAME\n\
+                    a very long t…  someone@examp…\n");
+        assert!(table.lines().all(|l| l.width() <= 30));
+    }
+}
@@ -0,0 +1,437 @@
+//! Format strings, e.g. for `pwx list --fmt` and `pwx getrec`
+//!
+//! `{name}` is replaced by the value of a field, `{{` and `}}` are
+//! literal braces. A missing field is an error, unless there is a default
+//! or it is inside a conditional.
+//!
+//! - `{url:-none}` uses `none` if the field is missing or empty
+//! - `{title|upper}` applies filters, left to right: `upper`, `lower`,
+//!   `trim`, `base64`, `hex`, `date:FORMAT` (strftime), and the
+//!   escapes `shell` (single quoted), `json` (a JSON string) and `url`
+//!   (percent encoded)
+//! - `{title:<20}`, `{title:>20}` and `{title:^20}` pad to a width,
+//!   `{title:*<20}` pads with `*` (any character but `-`) instead of
+//!   spaces, and `{title:.5}` truncates to a width, e.g. `{title:<8.8}`
+//!   is exactly 8 columns. Widths are display widths, e.g. CJK
+//!   characters are 2 columns wide
+//! - `{?url}...{/url}` is only printed if the field is set (not empty),
+//!   `{!url}...{/url}` only if it is not
+//!
+//! Filters see the raw value, e.g. `{uuid|hex}` is the 32 hex digits of
+//! the UUID and `{ctime|date:%Y}` formats the timestamp. The default is
+//! printed as is, without filters.
+
+use super::Fail;
+use super::uuid::Uuid;
+use super::chrono::{DateTime, FixedOffset, NaiveDateTime};
+use super::chrono::format::{Item, StrftimeItems};
+use super::serde_json;
+use super::unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// The value of a variable
+#[derive(Debug, Clone, PartialEq)]
+pub enum Var {
+    Text(String),
+    /// An UUID, and its text form (base58 or hyphenated)
+    Uuid(Uuid, String),
+    /// A timestamp, in the time zone it is shown in
+    Time(DateTime<FixedOffset>),
+}
+
+impl Var {
+    fn text(&self) -> String {
+        match *self {
+            Var::Text(ref s) | Var::Uuid(_, ref s) => s.clone(),
+            Var::Time(ref ts) => ts.format("%Y-%m-%d %H:%M:%S").to_string(),
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match *self {
+            Var::Uuid(ref uuid, _) => uuid.as_bytes().to_vec(),
+            _ => self.text().into_bytes(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match *self {
+            Var::Text(ref s) => s.is_empty(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// The fill, alignment, width and precision of a variable, like in Rust
+/// format strings
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Width {
+    fill: char,
+    align: Align,
+    width: usize,
+    precision: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Text(String),
+    Var {
+        name: String,
+        width: Option<Width>,
+        filters: Vec<(String, String)>,
+        default: Option<String>,
+    },
+    Section {
+        name: String,
+        negate: bool,
+        body: Vec<Node>,
+    },
+}
+
+/// An open section, its name and negation, and its nodes
+type Frame = (Option<(String, bool)>, Vec<Node>);
+
+/// A parsed format string
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+fn invalid(msg: String) -> Fail {
+    Fail::InvalidTemplate(msg)
+}
+
+const FILTERS: &[&str] = &["upper", "lower", "trim", "base64", "hex", "date", "shell", "json", "url"];
+
+fn parse_name(name: &str) -> Result<String, Fail> {
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(invalid(format!("invalid field name {:?}", name)));
+    }
+    Ok(name.to_owned())
+}
+
+fn parse_align(c: Option<char>) -> Option<Align> {
+    match c {
+        Some('<') => Some(Align::Left),
+        Some('>') => Some(Align::Right),
+        Some('^') => Some(Align::Center),
+        _ => None,
+    }
+}
+
+/// Parse `[[fill]align][width][.precision]`
+fn parse_width(spec: &str) -> Result<Width, Fail> {
+    let err = || invalid(format!("invalid width {:?}", spec));
+    let mut chars = spec.chars();
+    let first = chars.next();
+    let (fill, align, rest) = match (first, parse_align(first), parse_align(chars.next())) {
+        (Some(fill), _, Some(align)) => (fill, align, chars.as_str()),
+        (_, Some(align), _) => (' ', align, &spec[1..]),
+        _ => (' ', Align::Left, spec),
+    };
+    let (width, precision) = match rest.find('.') {
+        Some(idx) => (&rest[..idx], Some(rest[idx + 1..].parse().map_err(|_| err())?)),
+        None => (rest, None),
+    };
+    let width = match width {
+        "" if precision.is_some() => 0,
+        width => width.parse().map_err(|_| err())?,
+    };
+    Ok(Width { fill, align, width, precision })
+}
+
+fn parse_var(tag: &str) -> Result<Node, Fail> {
+    let (head, default) = match tag.find(":-") {
+        Some(idx) => (&tag[..idx], Some(tag[idx + 2..].to_owned())),
+        None => (tag, None),
+    };
+    let mut parts = head.split('|');
+    let first = parts.next().unwrap_or("");
+    let (name, width) = match first.find(':') {
+        Some(idx) => (&first[..idx], Some(parse_width(&first[idx + 1..])?)),
+        None => (first, None),
+    };
+
+    let mut filters = Vec::new();
+    for part in parts {
+        let (filter, arg) = match part.find(':') {
+            Some(idx) => (&part[..idx], &part[idx + 1..]),
+            None => (part, ""),
+        };
+        if !FILTERS.contains(&filter) {
+            return Err(invalid(format!("unknown filter {:?}", filter)));
+        }
+        if filter == "date" &&
+           (arg.is_empty() || StrftimeItems::new(arg).any(|item| item == Item::Error)) {
+            return Err(invalid(format!("invalid date format {:?}", arg)));
+        }
+        filters.push((filter.to_owned(), arg.to_owned()));
+    }
+    Ok(Node::Var { name: parse_name(name)?, width, filters, default })
+}
+
+impl Template {
+    /// Parse a format string, see the module documentation
+    pub fn parse(s: &str) -> Result<Template, Fail> {
+        // Open sections, the top level is the last element
+        let mut stack: Vec<Frame> = vec![(None, Vec::new())];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => return Err(invalid("unmatched }".to_owned())),
+                '{' => {
+                    let mut tag = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => tag.push(c),
+                            None => return Err(invalid("unterminated {".to_owned())),
+                        }
+                    }
+                    let nodes = &mut stack.last_mut().expect("template stack").1;
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(text.split_off(0)));
+                    }
+
+                    if let Some(name) = tag.strip_prefix('/') {
+                        match stack.pop() {
+                            Some((Some((open, negate)), body)) if open == name => {
+                                let parent = &mut stack.last_mut().expect("template stack").1;
+                                parent.push(Node::Section { name: open, negate, body });
+                            }
+                            _ => return Err(invalid(format!("unexpected {{/{}}}", name))),
+                        }
+                    } else if tag.starts_with('?') || tag.starts_with('!') {
+                        let name = parse_name(&tag[1..])?;
+                        stack.push((Some((name, tag.starts_with('!'))), Vec::new()));
+                    } else {
+                        nodes.push(parse_var(&tag)?);
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+
+        if stack.len() > 1 {
+            let name = stack.pop().and_then(|s| s.0).map(|s| s.0).unwrap_or_default();
+            return Err(invalid(format!("missing {{/{}}}", name)));
+        }
+        let mut nodes = stack.pop().expect("template stack").1;
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(Template { nodes })
+    }
+
+    /// Render the format string, `lookup` returns the value of a variable
+    pub fn render<F>(&self, lookup: F) -> Result<String, Fail>
+            where F: Fn(&str) -> Option<Var> {
+        let mut out = String::new();
+        render_nodes(&self.nodes, &lookup, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn render_nodes<F>(nodes: &[Node], lookup: &F, out: &mut String) -> Result<(), Fail>
+        where F: Fn(&str) -> Option<Var> {
+    for node in nodes {
+        match *node {
+            Node::Text(ref s) => out.push_str(s),
+            Node::Section { ref name, negate, ref body } => {
+                let set = lookup(name).map(|v| !v.is_empty()).unwrap_or(false);
+                if set != negate {
+                    render_nodes(body, lookup, out)?;
+                }
+            }
+            Node::Var { ref name, width, ref filters, ref default } => {
+                let value = match (lookup(name), default.as_ref()) {
+                    (Some(ref var), Some(default)) if var.is_empty() => default.clone(),
+                    (None, Some(default)) => default.clone(),
+                    (Some(var), _) => apply_filters(var, filters)?,
+                    (None, None) => return Err(Fail::MissingField(format!("field: {}", name))),
+                };
+                match width {
+                    Some(width) => out.push_str(&pad(&value, width)),
+                    None => out.push_str(&value),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Truncate to the precision and pad to the width, in display columns
+fn pad(s: &str, width: Width) -> String {
+    let text = match width.precision {
+        Some(precision) => {
+            let mut used = 0;
+            s.chars()
+                .take_while(|c| {
+                    used += c.width().unwrap_or(0);
+                    used <= precision
+                })
+                .collect()
+        }
+        None => s.to_owned(),
+    };
+
+    let fill = width.width.saturating_sub(text.width());
+    let (left, right) = match width.align {
+        Align::Left => (0, fill),
+        Align::Right => (fill, 0),
+        Align::Center => (fill / 2, fill - fill / 2),
+    };
+    let fill = width.fill.to_string();
+    format!("{}{}{}", fill.repeat(left), text, fill.repeat(right))
+}
+
+fn apply_filters(mut var: Var, filters: &[(String, String)]) -> Result<String, Fail> {
+    for (filter, arg) in filters {
+        let text = match filter.as_str() {
+            "upper" => var.text().to_uppercase(),
+            "lower" => var.text().to_lowercase(),
+            "trim" => var.text().trim().to_owned(),
+            "base64" => base64(&var.bytes()),
+            "hex" => var.bytes().iter().map(|b| format!("{:02x}", b)).collect(),
+            "date" => {
+                let ts = match var {
+                    Var::Time(ts) => ts,
+                    _ => {
+                        NaiveDateTime::parse_from_str(&var.text(), "%Y-%m-%d %H:%M:%S")
+                            .map(|ts| ts.and_utc().fixed_offset())
+                            .map_err(|_| invalid(format!("not a date: {}", var.text())))?
+                    }
+                };
+                ts.format(arg).to_string()
+            }
+            "shell" => format!("'{}'", var.text().replace('\'', "'\\''")),
+            "json" => serde_json::to_string(&var.text()).expect("json string"),
+            "url" => percent_encode(&var.text()),
+            _ => unreachable!("filters are checked by parse()"),
+        };
+        var = Var::Text(text);
+    }
+    Ok(var.text())
+}
+
+/// Base64 with padding (RFC 4648)
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Percent encode everything but unreserved characters (RFC 3986)
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, TimeZone};
+
+    fn lookup(name: &str) -> Option<Var> {
+        let var = match name {
+            "title" => Var::Text("git'hub".to_owned()),
+            "username" => Var::Text(String::new()),
+            "uuid" => {
+                let uuid = Uuid::parse_str("43fe1d0e-b65f-4e48-9abf-a1c5a1beeee8").unwrap();
+                Var::Uuid(uuid, "9PyDEwwoeWAdBP9fQzfKEF".to_owned())
+            }
+            "ctime" => {
+                let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+                Var::Time(tz.with_ymd_and_hms(2015, 5, 5, 12, 38, 1).unwrap())
+            }
+            "notes" => Var::Text("a \"b\"\nc".to_owned()),
+            _ => return None,
+        };
+        Some(var)
+    }
+
+    fn render(s: &str) -> Result<String, Fail> {
+        Template::parse(s)?.render(lookup)
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render("{uuid} {title} [{username}]\n").unwrap(),
+                   "9PyDEwwoeWAdBP9fQzfKEF git'hub []\n");
+        assert_eq!(render("{{{title}}}").unwrap(), "{git'hub}");
+        assert_eq!(render("{url:-none} {username:-nobody} {title:-x}").unwrap(), "none nobody git'hub");
+        assert_eq!(render("{title|upper} {uuid|hex}").unwrap(),
+                   "GIT'HUB 43fe1d0eb65f4e489abfa1c5a1beeee8");
+        assert_eq!(render("{ctime} {ctime|date:%Y-%m-%d}").unwrap(), "2015-05-05 12:38:01 2015-05-05");
+        assert_eq!(render("{ctime|date:%H%z}").unwrap(), "12+0200");
+        assert_eq!(render("{title|shell} {notes|json}").unwrap(), "'git'\\''hub' \"a \\\"b\\\"\\nc\"");
+        assert_eq!(render("{title|url} {title|lower|base64}").unwrap(), "git%27hub Z2l0J2h1Yg==");
+        assert_eq!(render("[{title:<8}][{title:>8}][{title:^9}]").unwrap(), "[git'hub ][ git'hub][ git'hub ]");
+        assert_eq!(render("[{title:.3}][{title:*<9}][{title:=>5.3}][{title:_^11}]").unwrap(),
+                   "[git][git'hub**][==git][__git'hub__]");
+        assert_eq!(render("{?url}url = {url}\n{/url}{!url}no url{/url}{?title}, {title}{/title}").unwrap(),
+                   "no url, git'hub");
+        assert_eq!(render("{?username}{username}{/username}").unwrap(), "");
+        assert_eq!(render("{url}").unwrap_err().to_string(), "Record has no field: url");
+        assert!(render("{title|date:%Y}").is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        for s in &["{title", "title}", "{}", "{title|nope}", "{ctime|date}", "{ctime|date:%Q}",
+                   "{?url}x", "{?url}x{/title}", "{/url}", "{title:<x}", "{ti tle}", "{title:}",
+                   "{title:.}", "{title:5.x}", "{title:ab5}"] {
+            assert!(Template::parse(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_pad_width() {
+        let width = |spec| parse_width(spec).unwrap();
+        assert_eq!(width("*^7.2"), Width { fill: '*', align: Align::Center, width: 7, precision: Some(2) });
+        assert_eq!(pad("日本語", width("<8")), "日本語  ");
+        assert_eq!(pad("日本語", width(">.5")), "日本");
+        assert_eq!(pad("日本語", width("-^9.4")), "--日本---");
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
@@ -0,0 +1,293 @@
+//! TOTP (RFC 6238) codes from the two-factor key field
+//!
+//! The record stores the base32 secret in field 0x1b, the settings are
+//! optional fields
+//!
+//! - 0x21 algorithm, 0 for SHA1 (default), 1 for SHA256, 2 for SHA512
+//! - 0x22 number of digits, defaults to 6
+//! - 0x23 time step in seconds, defaults to 30
+//! - 0x24 start time (time_t), defaults to 0
+
+use super::Fail;
+use super::db::{Field, Value};
+use super::record;
+use super::util::{read_uint, to_time_t};
+use super::base32::{self, Alphabet};
+use super::chrono::DateTime;
+use super::hmac::{Hmac, Mac};
+use super::sha1::Sha1;
+use super::sha2::{Sha256, Sha512};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// TOTP parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct Totp {
+    pub secret: Vec<u8>,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub period: u64,
+    pub t0: i64,
+}
+
+fn invalid(msg: &str) -> Fail {
+    Fail::InvalidTotp(msg.to_owned())
+}
+
+/// Decode a base32 secret, ignoring case, spaces and padding
+fn decode_secret(s: &str) -> Option<Vec<u8>> {
+    let s: String = s.chars()
+        .filter(|c| !c.is_whitespace() && *c != '=')
+        .collect::<String>()
+        .to_uppercase();
+    base32::decode(Alphabet::RFC4648 { padding: false }, &s).filter(|secret| !secret.is_empty())
+}
+
+/// Decode %XX escapes in an URI component
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|h| ::std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+impl Totp {
+    /// Read the TOTP parameters from a record, None if the record has
+    /// no two-factor key
+    pub fn from_record(rec: &[Field]) -> Option<Result<Totp, Fail>> {
+        let key = record::find(rec, 0x1b)?;
+        let secret = match decode_secret(&String::from_utf8_lossy(key.value().as_ref())) {
+            Some(secret) => secret,
+            None => return Some(Err(invalid("the two-factor key is not valid base32"))),
+        };
+        let uint = |typ| record::find(rec, typ).and_then(|f| read_uint(f.value().as_ref()));
+
+        let algorithm = match uint(0x21).unwrap_or(0) {
+            0 => Algorithm::Sha1,
+            1 => Algorithm::Sha256,
+            2 => Algorithm::Sha512,
+            _ => return Some(Err(invalid("unknown algorithm"))),
+        };
+        let digits = uint(0x22).unwrap_or(6);
+        if !(1..=10).contains(&digits) {
+            return Some(Err(invalid("invalid number of digits")));
+        }
+        let period = uint(0x23).unwrap_or(30);
+        if period == 0 {
+            return Some(Err(invalid("invalid time step")));
+        }
+        let t0 = record::time(rec, 0x24).map(|ts| ts.timestamp()).unwrap_or(0);
+
+        Some(Ok(Totp {
+            secret,
+            algorithm,
+            digits: digits as u32,
+            period,
+            t0,
+        }))
+    }
+
+    /// Parse an `otpauth://totp/` URI
+    pub fn from_uri(uri: &str) -> Result<Totp, Fail> {
+        if !uri.starts_with("otpauth://totp/") {
+            return Err(invalid("not an otpauth://totp/ URI"));
+        }
+        let mut totp = Totp {
+            secret: Vec::new(),
+            algorithm: Algorithm::Sha1,
+            digits: 6,
+            period: 30,
+            t0: 0,
+        };
+
+        let query = uri.split_once('?').map(|(_, q)| q).unwrap_or("");
+        for param in query.split('&') {
+            let (key, val) = param.split_once('=').unwrap_or((param, ""));
+            let val = percent_decode(val);
+            match key {
+                "secret" => {
+                    totp.secret = decode_secret(&val)
+                        .ok_or_else(|| invalid("the secret is not valid base32"))?;
+                }
+                "algorithm" => {
+                    totp.algorithm = match val.to_uppercase().as_str() {
+                        "SHA1" => Algorithm::Sha1,
+                        "SHA256" => Algorithm::Sha256,
+                        "SHA512" => Algorithm::Sha512,
+                        _ => return Err(invalid("unknown algorithm")),
+                    };
+                }
+                "digits" => {
+                    totp.digits = val.parse()
+                        .ok()
+                        .filter(|d| (1..=10).contains(d))
+                        .ok_or_else(|| invalid("invalid number of digits"))?;
+                }
+                "period" => {
+                    totp.period = val.parse()
+                        .ok()
+                        .filter(|p| (1..=255).contains(p))
+                        .ok_or_else(|| invalid("invalid period"))?;
+                }
+                _ => (),
+            }
+        }
+
+        if totp.secret.is_empty() {
+            return Err(invalid("missing secret"));
+        }
+        Ok(totp)
+    }
+
+    /// Store the TOTP parameters in a record, the defaults are not stored
+    pub fn set(&self, rec: &mut Vec<Field>) -> Result<(), Fail> {
+        let secret = base32::encode(Alphabet::RFC4648 { padding: false }, &self.secret);
+        record::set_field(rec, Field::from(0x1b, Value::from(secret.into_bytes())))?;
+
+        let algorithm = match self.algorithm {
+            Algorithm::Sha1 => 0,
+            Algorithm::Sha256 => 1,
+            Algorithm::Sha512 => 2,
+        };
+        let settings = [(0x21, algorithm, 0), (0x22, self.digits as u8, 6), (0x23, self.period as u8, 30)];
+        for &(typ, val, default) in &settings {
+            if val == default {
+                record::remove_field(rec, typ)?;
+            } else {
+                record::set_field(rec, Field::from(typ, Value::from(vec![val])))?;
+            }
+        }
+
+        match DateTime::from_timestamp(self.t0, 0) {
+            Some(t0) if self.t0 != 0 => {
+                let val = Value::from(to_time_t(&t0)?);
+                record::set_field(rec, Field::from(0x24, val))
+            }
+            _ => record::remove_field(rec, 0x24),
+        }
+    }
+
+    /// The code for the given time (seconds since the epoch)
+    pub fn code(&self, now: i64) -> String {
+        let counter = if now > self.t0 {
+            (now - self.t0) as u64 / self.period
+        } else {
+            0
+        };
+        let msg = counter.to_be_bytes();
+
+        let mac = match self.algorithm {
+            Algorithm::Sha1 => {
+                let mut mac = Hmac::<Sha1>::new_from_slice(&self.secret).expect("HMAC key");
+                mac.update(&msg);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Algorithm::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC key");
+                mac.update(&msg);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Algorithm::Sha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(&self.secret).expect("HMAC key");
+                mac.update(&msg);
+                mac.finalize().into_bytes().to_vec()
+            }
+        };
+
+        // Dynamic truncation, RFC 4226 section 5.3
+        let offset = (mac[mac.len() - 1] & 0xf) as usize;
+        let bin = u32::from_be_bytes([mac[offset] & 0x7f,
+                                      mac[offset + 1],
+                                      mac[offset + 2],
+                                      mac[offset + 3]]);
+        let code = u64::from(bin) % 10u64.pow(self.digits);
+        format!("{:0width$}", code, width = self.digits as usize)
+    }
+
+    /// The seconds until the code for the given time changes
+    pub fn remaining(&self, now: i64) -> u64 {
+        if now > self.t0 {
+            self.period - (now - self.t0) as u64 % self.period
+        } else {
+            (self.t0 - now) as u64 + self.period
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn totp(secret: &[u8], algorithm: Algorithm) -> Totp {
+        Totp {
+            secret: secret.to_vec(),
+            algorithm,
+            digits: 8,
+            period: 30,
+            t0: 0,
+        }
+    }
+
+    #[test]
+    fn test_rfc6238() {
+        let sha1 = totp(b"12345678901234567890", Algorithm::Sha1);
+        let sha256 = totp(b"12345678901234567890123456789012", Algorithm::Sha256);
+        let sha512 = totp(b"1234567890123456789012345678901234567890123456789012345678901234",
+                          Algorithm::Sha512);
+        let vectors = [(59, "94287082", "46119246", "90693936"),
+                       (1111111109, "07081804", "68084774", "25091201"),
+                       (1111111111, "14050471", "67062674", "99943326"),
+                       (1234567890, "89005924", "91819424", "93441116"),
+                       (2000000000, "69279037", "90698825", "38618901"),
+                       (20000000000, "65353130", "77737706", "47863826")];
+        for &(time, c1, c256, c512) in &vectors {
+            assert_eq!(sha1.code(time), c1);
+            assert_eq!(sha256.code(time), c256);
+            assert_eq!(sha512.code(time), c512);
+        }
+        assert_eq!(sha1.remaining(0), 30);
+        assert_eq!(sha1.remaining(59), 1);
+        assert_eq!(sha1.remaining(60), 30);
+    }
+
+    #[test]
+    fn test_uri() {
+        let t = Totp::from_uri("otpauth://totp/Example:alice%40example.com?secret=gezd%20gnbv&issuer=Example&digits=8&algorithm=sha256&period=60").unwrap();
+        assert_eq!(t, Totp { secret: b"12345".to_vec(), algorithm: Algorithm::Sha256, digits: 8, period: 60, t0: 0 });
+        assert!(Totp::from_uri("otpauth://hotp/x?secret=GEZDGNBV").is_err());
+        assert!(Totp::from_uri("otpauth://totp/x?issuer=x").is_err());
+        assert!(Totp::from_uri("otpauth://totp/x?secret=GEZDGNBV&digits=0").is_err());
+    }
+
+    #[test]
+    fn test_record() {
+        let mut rec = Vec::new();
+        let t = totp(b"12345678901234567890", Algorithm::Sha1);
+        t.set(&mut rec).unwrap();
+        assert_eq!(rec.len(), 2);
+        assert_eq!(format!("{}", rec[0]), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(Totp::from_record(&rec).unwrap().unwrap(), t);
+        assert_eq!(Totp::from_record(&rec).unwrap().unwrap().code(59), "94287082");
+        assert!(Totp::from_record(&[]).is_none());
+    }
+}
@@ -31,8 +31,11 @@ impl Key {
         self.k.decrypt_block_b2b(i, o)
     }
 
-    #[allow(dead_code)]
-    fn encrypt(&self, data_in: &[u8], out: &mut [u8]) {
+    pub fn encrypt(&self, data_in: &[u8], out: &mut [u8]) {
+        if data_in.len() < 16 || out.len() < 16 {
+            panic!("Invalid twofish block size");
+        }
+
         let i = GenericArray::from_slice(data_in);
         let o = GenericArray::from_mut_slice(&mut out[..16]);
         self.k.encrypt_block_b2b(i, o)
@@ -3,9 +3,16 @@ extern crate byteorder;
 
 use sha2::Sha256;
 use sha2::Digest;
-use super::SHA256_SIZE;
-use byteorder::{LittleEndian, ReadBytesExt};
-use chrono::naive::NaiveDateTime;
+use super::{Fail, SHA256_SIZE};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::naive::{NaiveDate, NaiveDateTime};
+use chrono::{DateTime, Duration, Local, TimeZone, Utc};
+use uuid::Uuid;
+use std::str::from_utf8;
+use rand::Rng;
+use rand::rngs::OsRng;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
 
 /// Generate the SHA-256 value of a password after several rounds of
 /// stretching. If the salt is too short, this returns None.
@@ -30,40 +37,270 @@ pub fn stretch_pass(salt: &[u8], pass: &[u8], iter: u32) -> Option<[u8; SHA256_S
     Some(hash.into())
 }
 
+/// Fold a string for case insensitive matching. Each character is
+/// uppercased and then lowercased, which is close to full Unicode case
+/// folding, e.g. "ß", "ẞ" and "SS" fold to "ss", and "ς" to "σ".
+///
+/// With `strip_accents` the string is first decomposed (NFKD) and the
+/// combining marks are removed, e.g. "é" becomes "e" and "㎒" "mhz".
+/// Letters without a decomposition, such as "ø" or "æ", are kept.
+pub fn fold_case(s: &str, strip_accents: bool) -> String {
+    let fold = |c: char| c.to_uppercase().flat_map(|c| c.to_lowercase());
+    if strip_accents {
+        s.nfkd().filter(|c| !is_combining_mark(*c)).flat_map(fold).collect()
+    } else {
+        s.chars().flat_map(fold).collect()
+    }
+}
+
 /// Matching function for filters - this behaves as
 /// a case insensitive substring find. Except it
 /// returns false if any of the arguments is empty.
 pub fn fuzzy_eq(needle: &str, hay: &str) -> bool {
+    fuzzy_match(needle, hay, false)
+}
+
+/// Like `fuzzy_eq()`, optionally ignoring accents, see `fold_case()`
+pub fn fuzzy_match(needle: &str, hay: &str, ignore_accents: bool) -> bool {
     if needle.is_empty() || hay.is_empty() {
         return false;
     }
 
-    // FIXME: unicode?
-    let h = hay.to_ascii_lowercase();
-    let n = needle.to_ascii_lowercase();
-    h.find(&n).is_some()
+    let h = fold_case(hay, ignore_accents);
+    let n = fold_case(needle, ignore_accents);
+    h.contains(&n)
 }
 
-/// Read binary data as time_t, i.e. decode 32bit or 64bit sequences
-/// as unsigned little endian. [sec. 3.1.3]
-pub fn from_time_t(b: &[u8]) -> Option<NaiveDateTime> {
+/// Read binary data as time_t, i.e. decode 32bit (unsigned) or 64bit
+/// (signed) little endian seconds since the epoch. [sec. 3.1.3]
+///
+/// Fails with `Fail::InvalidValue` for other lengths, and negative or out
+/// of range values.
+pub fn from_time_t(b: &[u8]) -> Result<DateTime<Utc>, Fail> {
     let mut b_r = b;
-    if b.len() == 4 {
-        b_r.read_u32::<LittleEndian>()
-           .map(|val| NaiveDateTime::from_timestamp(val as i64, 0))
-           .ok()
-    } else if b.len() == 8 {
-        b_r.read_u64::<LittleEndian>()
-           .map(|val| NaiveDateTime::from_timestamp(val as i64, 0))
-           .ok()
+    let secs = match b.len() {
+        4 => b_r.read_u32::<LittleEndian>()? as i64,
+        8 => b_r.read_i64::<LittleEndian>()?,
+        len => return Err(Fail::InvalidValue(format!("time_t of {} bytes", len))),
+    };
+    if secs < 0 {
+        return Err(Fail::InvalidValue(format!("negative time_t {}", secs)));
+    }
+    DateTime::from_timestamp(secs, 0)
+        .ok_or_else(|| Fail::InvalidValue(format!("time_t out of range {}", secs)))
+}
+
+/// Parse a duration, a number with a unit `h` (hours), `d` (days), `w`
+/// (weeks) or `y` (365 days). A number alone is days.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, "d"),
+    };
+    let hours = match unit {
+        "h" => 1,
+        "d" => 24,
+        "w" => 7 * 24,
+        "y" => 365 * 24,
+        _ => return None,
+    };
+    num.parse::<i64>()
+        .ok()
+        .and_then(|n| n.checked_mul(hours))
+        .and_then(Duration::try_hours)
+}
+
+/// Parse a point in time, as UTC. This is a duration before `now` (see
+/// `parse_duration()`), `today`, a date `YYYY-MM-DD` or time
+/// `YYYY-MM-DDTHH:MM[:SS]` in the local time zone, or RFC 3339.
+pub fn parse_when(s: &str, now: &DateTime<Utc>) -> Result<DateTime<Utc>, Fail> {
+    if let Some(duration) = parse_duration(s) {
+        return now.checked_sub_signed(duration)
+            .ok_or_else(|| Fail::InvalidValue(format!("duration out of range: {}", s)));
+    }
+    if let Ok(ts) = DateTime::parse_from_rfc3339(s) {
+        return Ok(ts.with_timezone(&Utc));
+    }
+
+    let local = if s == "today" {
+        now.with_timezone(&Local).date_naive().and_hms_opt(0, 0, 0)
+    } else if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        date.and_hms_opt(0, 0, 0)
+    } else {
+        ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+            .iter()
+            .filter_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+            .next()
+    };
+    local.and_then(|ts| Local.from_local_datetime(&ts).earliest())
+        .map(|ts| ts.with_timezone(&Utc))
+        .ok_or_else(|| Fail::InvalidValue(format!("not a date or duration: {}", s)))
+}
+
+/// Describe the time between `ts` and `now`, e.g. "3 months ago" or
+/// "in 2 days"
+pub fn format_relative(ts: &DateTime<Utc>, now: &DateTime<Utc>) -> String {
+    let secs = (*now - *ts).num_seconds();
+    let abs = secs.abs();
+    let (n, unit) = if abs < 60 {
+        return "just now".to_owned();
+    } else if abs < 3600 {
+        (abs / 60, "minute")
+    } else if abs < 86400 {
+        (abs / 3600, "hour")
+    } else if abs < 30 * 86400 {
+        (abs / 86400, "day")
+    } else if abs < 365 * 86400 {
+        (abs / (30 * 86400), "month")
+    } else {
+        (abs / (365 * 86400), "year")
+    };
+    let plural = if n == 1 { "" } else { "s" };
+    if secs >= 0 {
+        format!("{} {}{} ago", n, unit, plural)
+    } else {
+        format!("in {} {}{}", n, unit, plural)
+    }
+}
+
+/// Decode an unsigned little endian integer of up to 8 bytes
+pub fn read_uint(b: &[u8]) -> Option<u64> {
+    if b.is_empty() || b.len() > 8 {
+        return None;
+    }
+    Some(b.iter().rev().fold(0u64, |acc, byte| (acc << 8) | *byte as u64))
+}
+
+/// Encode a timestamp as a little endian time_t, 32bit if it fits, 64bit
+/// otherwise. Times before the epoch are an error, like in
+/// `from_time_t()`. [sec. 3.1.3]
+pub fn to_time_t(ts: &DateTime<Utc>) -> Result<Vec<u8>, Fail> {
+    let secs = ts.timestamp();
+    if secs < 0 {
+        return Err(Fail::InvalidValue(format!("negative time_t {}", secs)));
+    }
+    let mut b = Vec::with_capacity(8);
+    if secs <= u32::MAX as i64 {
+        b.write_u32::<LittleEndian>(secs as u32)
+    } else {
+        b.write_i64::<LittleEndian>(secs)
+    }.expect("BUG writing to a Vec");
+    Ok(b)
+}
+
+/// Parse the recently used entries header field, a 2 hex character count
+/// followed by the UUIDs as 32 hex characters. [sec. 3.2 note 14]
+pub fn parse_recent(b: &[u8]) -> Option<Vec<Uuid>> {
+    let s = from_utf8(b).ok()?;
+    if s.len() < 2 || !s.is_ascii() {
+        return None;
+    }
+    let count = usize::from_str_radix(&s[..2], 16).ok()?;
+    let uuids = &s[2..];
+    if uuids.len() < count * 32 {
+        return None;
+    }
+    (0..count)
+        .map(|i| Uuid::parse_str(&uuids[i * 32..(i + 1) * 32]).ok())
+        .collect()
+}
+
+/// Encode a list of UUIDs as a recently used entries header field,
+/// at most 255 entries are kept.
+pub fn format_recent(recent: &[Uuid]) -> String {
+    let recent = &recent[..recent.len().min(255)];
+    let mut s = format!("{:02x}", recent.len());
+    for uuid in recent {
+        s.push_str(&uuid.simple().to_string());
+    }
+    s
+}
+
+/// The default username from the preferences header field (0x02), if
+/// the UseDefaultUser preference (B 4) is set. The default username is
+/// the string preference S 3, strings are enclosed in a delimiter
+/// character, e.g. `B 4 1 S 3 "user" `.
+pub fn default_username(prefs: &str) -> Option<String> {
+    let mut use_default = false;
+    let mut username = None;
+    let mut chars = prefs.chars().peekable();
+
+    loop {
+        while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+        let kind = match chars.next() {
+            Some(kind) => kind,
+            None => break,
+        };
+        let mut word = || -> Option<String> {
+            while chars.peek()?.is_whitespace() {
+                chars.next();
+            }
+            let mut w = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                w.push(c);
+                chars.next();
+            }
+            Some(w)
+        };
+        let id = word()?;
+        match kind {
+            'B' | 'I' => {
+                let val = word()?;
+                if kind == 'B' && id == "4" {
+                    use_default = val == "1";
+                }
+            }
+            'S' => {
+                while chars.peek()?.is_whitespace() {
+                    chars.next();
+                }
+                let delim = chars.next()?;
+                let val: String = chars.by_ref().take_while(|c| *c != delim).collect();
+                if id == "3" {
+                    username = Some(val);
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    if use_default {
+        username.filter(|u| !u.is_empty())
     } else {
         None
     }
 }
 
+/// Generate a random password with lowercase and uppercase letters,
+/// digits and symbols, at least one of each
+pub fn generate_password(len: usize) -> String {
+    const LOWER: &str = "abcdefghijkmnopqrstuvwxyz";
+    const UPPER: &str = "ABCDEFGHJKLMNPQRSTUVWXYZ";
+    const DIGITS: &str = "23456789";
+    const SYMBOLS: &str = "!#$%&*+-=?@^_~";
+    let classes = [LOWER, UPPER, DIGITS, SYMBOLS];
+    let all: Vec<char> = classes.concat().chars().collect();
+
+    loop {
+        let password: String = (0..len).map(|_| all[OsRng.gen_range(0..all.len())]).collect();
+        if len < classes.len() ||
+           classes.iter().all(|class| password.chars().any(|c| class.contains(c))) {
+            return password;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::fuzzy_eq;
+    use super::{fuzzy_eq, fuzzy_match, fold_case, parse_recent, format_recent, default_username,
+                generate_password, format_relative, from_time_t, to_time_t, parse_duration, parse_when};
+    use uuid::Uuid;
+    use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 
     #[test]
     fn test_fuzzy_eq() {
@@ -74,4 +311,114 @@ mod tests {
         assert_eq!(fuzzy_eq("needle", "http://nEedle"), true);
     }
 
+    #[test]
+    fn test_fuzzy_unicode() {
+        assert!(fuzzy_eq("müller", "Hans MÜLLER"));
+        assert!(fuzzy_eq("STRASSE", "Hauptstraße"));
+        assert!(fuzzy_eq("ærø", "ÆRØ"));
+        assert!(fuzzy_eq("москва", "МОСКВА"));
+        assert!(fuzzy_eq("ΟΔΟΣ", "οδος"));
+        assert!(fuzzy_eq("σοφός", "ΣΟΦΌΣ"));
+        assert!(!fuzzy_eq("muller", "Müller"));
+
+        assert!(fuzzy_match("muller", "Müller", true));
+        assert!(fuzzy_match("Müller", "MULLER", true));
+        assert!(fuzzy_match("σοφοσ", "ΣΟΦΌΣ", true));
+        assert!(fuzzy_match("fi", "ﬁle", true));
+        assert!(!fuzzy_match("aero", "Ærø", true));
+        assert_eq!(fold_case("Crème Brûlée", true), "creme brulee");
+        assert!(fuzzy_match("mhz", "100 ㎒", true));
+        assert!(fuzzy_match("hilbert", "ℍilbert", true));
+    }
+
+    #[test]
+    fn test_recent() {
+        assert_eq!(parse_recent(b"00"), Some(vec![]));
+        assert_eq!(parse_recent(b""), None);
+        assert_eq!(parse_recent(b"02"), None);
+        assert_eq!(parse_recent(b"0z"), None);
+
+        let a = Uuid::parse_str("43fe1d0e-b65f-4e48-9abf-a1c5a1beeee8").unwrap();
+        let b = Uuid::parse_str("63a19136-46d9-4f75-827b-5312574233e8").unwrap();
+        let s = format_recent(&[a, b]);
+        assert_eq!(s, "0243fe1d0eb65f4e489abfa1c5a1beeee863a1913646d94f75827b5312574233e8");
+        assert_eq!(parse_recent(s.as_bytes()), Some(vec![a, b]));
+        assert_eq!(parse_recent(s.to_uppercase().as_bytes()), Some(vec![a, b]));
+    }
+
+    #[test]
+    fn test_default_username() {
+        assert_eq!(default_username("B 4 1 I 12 20 S 3 \"some user\" "), Some("some user".to_owned()));
+        assert_eq!(default_username("S 3 |us\"er| B 4 1"), Some("us\"er".to_owned()));
+        assert_eq!(default_username("B 4 0 S 3 \"user\" "), None);
+        assert_eq!(default_username("B 4 1 "), None);
+        assert_eq!(default_username(""), None);
+    }
+
+    #[test]
+    fn test_generate_password() {
+        let password = generate_password(20);
+        assert_eq!(password.len(), 20);
+        assert!(password.chars().any(|c| c.is_ascii_digit()));
+        assert!(password.chars().any(|c| c.is_ascii_uppercase()));
+        assert_ne!(password, generate_password(20));
+    }
+
+    #[test]
+    fn test_format_relative() {
+        let now = Utc.with_ymd_and_hms(2020, 6, 1, 12, 0, 0).unwrap();
+        let ago = |secs| format_relative(&(now - chrono::Duration::seconds(secs)), &now);
+        assert_eq!(ago(30), "just now");
+        assert_eq!(ago(60), "1 minute ago");
+        assert_eq!(ago(3 * 3600 + 10), "3 hours ago");
+        assert_eq!(ago(2 * 86400), "2 days ago");
+        assert_eq!(ago(100 * 86400), "3 months ago");
+        assert_eq!(ago(800 * 86400), "2 years ago");
+        assert_eq!(ago(-86400), "in 1 day");
+    }
+
+    #[test]
+    fn test_time_t() {
+        let ts = Utc.with_ymd_and_hms(2020, 2, 2, 12, 0, 0).unwrap();
+        assert_eq!(from_time_t(&[0xc0, 0xb9, 0x36, 0x5e]).unwrap(), ts);
+        assert_eq!(from_time_t(&[0xc0, 0xb9, 0x36, 0x5e, 0, 0, 0, 0]).unwrap(), ts);
+        assert_eq!(from_time_t(&[0xff; 4]).unwrap().timestamp(), u32::MAX as i64);
+        assert_eq!(to_time_t(&ts).unwrap(), vec![0xc0, 0xb9, 0x36, 0x5e]);
+        assert_eq!(to_time_t(&Utc.with_ymd_and_hms(1969, 12, 31, 23, 59, 59).unwrap()).unwrap_err().to_string(),
+                   "Invalid value: negative time_t -1");
+
+        // Beyond 2106 needs 64 bits
+        let future = Utc.with_ymd_and_hms(2200, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(to_time_t(&future).unwrap().len(), 8);
+        assert_eq!(from_time_t(&to_time_t(&future).unwrap()).unwrap(), future);
+
+        assert_eq!(from_time_t(&[0xff; 8]).unwrap_err().to_string(), "Invalid value: negative time_t -1");
+        assert!(from_time_t(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]).is_err());
+        assert!(from_time_t(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_parse_when() {
+        let now = Utc.with_ymd_and_hms(2020, 6, 1, 12, 0, 0).unwrap();
+        assert_eq!(parse_duration("30d"), Some(chrono::Duration::days(30)));
+        assert_eq!(parse_duration("2w"), Some(chrono::Duration::days(14)));
+        assert_eq!(parse_duration("12h"), Some(chrono::Duration::hours(12)));
+        assert_eq!(parse_duration("7"), Some(chrono::Duration::days(7)));
+        assert_eq!(parse_duration("3m"), None);
+        assert_eq!(parse_duration("d"), None);
+
+        assert_eq!(parse_when("30d", &now).unwrap(), now - chrono::Duration::days(30));
+        assert_eq!(parse_when("2020-05-01T10:00:00+02:00", &now).unwrap(),
+                   Utc.with_ymd_and_hms(2020, 5, 1, 8, 0, 0).unwrap());
+        let local = |ts: NaiveDateTime| -> DateTime<Utc> {
+            Local.from_local_datetime(&ts).earliest().unwrap().with_timezone(&Utc)
+        };
+        let may = NaiveDate::from_ymd_opt(2020, 5, 1).unwrap();
+        assert_eq!(parse_when("2020-05-01", &now).unwrap(), local(may.and_hms_opt(0, 0, 0).unwrap()));
+        assert_eq!(parse_when("2020-05-01T10:30", &now).unwrap(), local(may.and_hms_opt(10, 30, 0).unwrap()));
+        assert!(parse_when("soon", &now).is_err());
+        assert!(parse_when("2020-13-01", &now).is_err());
+        assert!(parse_when("999999999999y", &now).is_err());
+    }
+
 }
@@ -2,8 +2,17 @@
 // Tests for the pwx binary
 //
 
+extern crate pwx;
+
+extern crate chrono;
+extern crate serde_json;
+
+use pwx::{PwxReader, Value};
+use chrono::Utc;
+use std::path::Path;
 use std::process::Command;
 use std::env::current_exe;
+use std::io::Write;
 
 macro_rules! pwxrun {
     ($($arg:expr),*) => {{
@@ -92,6 +101,217 @@ fn list_filter() {
     assert_eq!(sout.trim().split('\n').count(), 1);
 }
 
+// Filter with a query expression
+#[test]
+fn list_where() {
+    let output = pwxrun!("list", "--where", "group~\"agroup\" and not url:\"\"");
+    let sout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(sout.trim().split('\n').collect::<Vec<_>>(), vec!["DJZsgbwbKNPkuQnB8btPZR testthis [testuser]"]);
+
+    let output = pwxrun!("list", "-W", "title=~\"^(face|git)\" or (username:testuser and ctime < 2016-01-01)");
+    let sout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(sout.trim().split('\n').count(), 3);
+
+    let output = pwxrun!("list", "-W", "title:github or");
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Invalid query: unexpected end of query\n");
+}
+
+#[test]
+fn list_rank() {
+    // "gh" is not a substring, but a subsequence of github
+    assert_eq!(String::from_utf8_lossy(&pwxrun!("list", "gh").stdout), "");
+    let output = pwxrun!("list", "--rank", "gh");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "DZhUE1kC7Tc7hNRY3ZXadS github [devy]\n");
+
+    // Title matches rank before notes and URL matches
+    let output = pwxrun!("list", "--rank", "-F", "{title}\n", "t");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "testthis\ngithub\nfacebook\n");
+    let output = pwxrun!("list", "--rank", "--limit", "1", "-F", "{title}\n", "t");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "testthis\n");
+    let output = pwxrun!("list", "--limit", "2");
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 2);
+
+    // The top hit can be passed to get
+    let output = pwxrun!("list", "--rank", "--limit", "1", "gh");
+    let line = String::from_utf8_lossy(&output.stdout).trim_end().to_owned();
+    let output = pwxrun!("get", &line, "password");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "devy.password\n");
+}
+
+#[test]
+fn list_sort() {
+    let output = pwxrun!("list", "--sort", "title", "-F", "{title}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "facebook\ngithub\ntestthis\n");
+    let output = pwxrun!("list", "--sort", "title", "--reverse", "--limit", "2", "-F", "{title}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "testthis\ngithub\n");
+
+    // Equal keys keep the file order
+    let output = pwxrun!("list", "--sort", "group", "--reverse", "-F", "{title}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "facebook\ntestthis\ngithub\n");
+
+    // Expiry from the expiry interval
+    let output = pwxrun!("list", "--sort", "expiry", "-F", "{title}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "testthis\ngithub\nfacebook\n");
+
+    let output = pwxrun!("list", "--sort", "size");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn list_columns() {
+    // Not a terminal, values are tab separated
+    let output = pwxrun!("list", "--columns", "title,username,group", "--sort", "title");
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+               "facebook\tsome@email.com\tsocial\ngithub\tdevy\tAGroup\ntestthis\ttestuser\tAGroup\n");
+
+    let output = pwxrun!("list", "--columns", "title,size");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn output_formats() {
+    let output = pwxrun!("list", "--output", "jsonl");
+    let records: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0]["id"], "9PyDEwwoeWAdBP9fQzfKEF");
+    assert_eq!(records[0]["uuid"], "43fe1d0e-b65f-4e48-9abf-a1c5a1beeee8");
+    assert_eq!(records[0]["ptime"], "2017-02-10T11:52:52Z");
+    assert_eq!(records[1]["expiry_interval"], 90);
+    // Secrets only with --reveal
+    assert!(records.iter().all(|r| r.get("password").is_none()));
+    let output = pwxrun!("list", "--output", "json", "--reveal", "-T", "github");
+    let records: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(records[0]["password"], "devy.password");
+
+    let output = pwxrun!("list", "-o", "csv", "--columns", "id,title,notes", "--sort", "title", "--limit", "2");
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+               "id,title,notes\n9PyDEwwoeWAdBP9fQzfKEF,facebook,Some notes\nDZhUE1kC7Tc7hNRY3ZXadS,github,\n");
+    let output = pwxrun!("list", "-o", "tsv", "-T", "github");
+    let sout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = sout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("id\tuuid\tgroup\ttitle\t"));
+    assert!(lines[1].starts_with("DZhUE1kC7Tc7hNRY3ZXadS\t65be679a-bc37-4f10-b986-c55d2cbbea95\tAGroup\tgithub\t"));
+
+    let output = pwxrun!("list", "-o", "nul", "-F", "{title}\n", "--sort", "title");
+    assert_eq!(output.stdout, b"facebook\0github\0testthis\0");
+
+    // The field given to get is included, even if it is a secret
+    let output = pwxrun!("get", "-o", "json", "DZhU", "password");
+    let record: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(record, serde_json::json!({"id": "DZhUE1kC7Tc7hNRY3ZXadS", "password": "devy.password"}));
+    let output = pwxrun!("getrec", "-o", "json", "DZhU");
+    let record: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(record["title"], "github");
+    assert!(record.get("password").is_none());
+    assert!(!pwxrun!("getrec", "DZhU").status.success());
+
+    let output = pwxrun!("info", "-o", "json");
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["uuid"], "ed960292-fc6f-43ac-b236-9b090cc9e030");
+
+    let output = pwxrun!("expiring", "-o", "json");
+    let records: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(records[2]["id"], "9PyDEwwoeWAdBP9fQzfKEF");
+    assert_eq!(records[2]["expires"], "2017-05-11T11:52:52Z");
+    assert!(records[2]["days"].as_i64().unwrap() < 0);
+
+    // show --json is show --output json
+    let output = pwxrun!("show", "-o", "json", "9PyD");
+    assert_eq!(output.stdout, pwxrun!("show", "--json", "9PyD").stdout);
+    let record: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(record["id"], "9PyDEwwoeWAdBP9fQzfKEF");
+    let output = pwxrun!("show", "-o", "csv", "9PyD");
+    let sout = String::from_utf8_lossy(&output.stdout);
+    assert!(sout.starts_with("section,name,label,value\n"));
+    assert!(sout.contains(",username,Username,some@email.com\n"));
+    assert!(!sout.contains("password\n"));
+
+    assert!(!pwxrun!("list", "-o", "xml").status.success());
+    assert!(!pwxrun!("rm", "-o", "json", "9PyD").status.success());
+}
+
+// Times are shown in local time, or UTC with --utc
+#[test]
+fn time_zones() {
+    let pwx = current_exe().unwrap().parent().unwrap().parent().unwrap().join("pwx");
+    let run = |args: &[&str]| {
+        let output = Command::new(&pwx)
+            .env("PWX_PASSWORD", "test")
+            .env("PWX_DATABASE", "tests/test.psafe3")
+            .env("TZ", "EST5")
+            .args(args)
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(run(&["getrec", "9PyD", "{ptime} {ptime|date:%H:%M%z}\n"]),
+               "2017-02-10 06:52:52 06:52-0500\n");
+    assert_eq!(run(&["getrec", "--utc", "9PyD", "{ptime} {ptime|date:%H:%M%z}\n"]),
+               "2017-02-10 11:52:52 11:52+0000\n");
+    assert!(run(&["expiring", "--expired", "-T", "face"]).contains("\t2017-05-11T06:52:52-05:00\t"));
+    assert!(run(&["show", "--json", "9PyD"]).contains("2017-02-10T11:52:52Z"));
+}
+
+#[test]
+fn list_fmt_extended() {
+    let output = pwxrun!("list", "--sort", "title",
+                         "-F", "{title|upper} {url:-none} {ctime|date:%Y:-never}{!group} (no group){/group}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+               "FACEBOOK https://facebook.com never\nGITHUB none 2015\nTESTTHIS www.what.com 2015\n");
+
+    let output = pwxrun!("getrec", "DZhU", "{uuid|hex} {password|shell} {{{title|json}}}{?email} {email}{/email}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+               "65be679abc374f10b986c55d2cbbea95 'devy.password' {\"github\"}");
+
+    let output = pwxrun!("list", "-F", "{title|nope}");
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Invalid format string: unknown filter \"nope\"\n");
+}
+
+#[test]
+fn list_dates() {
+    let titles = |output: std::process::Output| String::from_utf8_lossy(&output.stdout).into_owned();
+    assert_eq!(titles(pwxrun!("list", "-F", "{title}\n", "--modified-since", "2016-01-01")), "facebook\n");
+    assert_eq!(titles(pwxrun!("list", "-F", "{title}\n", "--modified-before", "2016-01-01T00:00:00Z")),
+               "testthis\n");
+    assert_eq!(titles(pwxrun!("list", "-F", "{title}\n", "--created-since", "2015-05-05T12:40:00+02:00",
+                              "--created-before", "30d")),
+               "github\n");
+    // The expiry is computed from the expiry interval
+    assert_eq!(titles(pwxrun!("list", "-F", "{title}\n", "--expires-before", "2016-01-01")),
+               "testthis\ngithub\n");
+    assert_eq!(titles(pwxrun!("list", "-F", "{title}\n", "--expires-within", "2w")), "");
+    assert_eq!(titles(pwxrun!("list", "-F", "{title}\n", "--accessed-since", "2000-01-01")), "");
+
+    let output = pwxrun!("list", "--changed-since", "soon");
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr),
+               "--changed-since: Invalid value: not a date or duration: soon\n");
+    assert!(!pwxrun!("list", "--expires-within", "2020-01-01").status.success());
+
+    // Malformed times are reported, not a crash
+    let db = tmpdb("dates");
+    edit_db(&db, |_, records| {
+        records[2].retain(|f| f.typ() != 0x07);
+        records[2].push(pwx::Field::from(0x07, Value::from(vec![1, 2, 3])));
+    });
+    assert_eq!(titles(pwxrun!(&db, "list", "-F", "{title}\n")), "facebook\ntestthis\ngithub\n");
+    let output = pwxrun!(&db, "list", "-F", "{title}\n", "--password-age", "10");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "facebook\ntestthis\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr),
+               "Warning: skipping record DZhUE1kC7Tc7hNRY3ZXadS: Invalid value: ctime: time_t of 3 bytes\n");
+    let output = pwxrun!(&db, "list", "-F", "{title}\n", "--created-since", "2000-01-01");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "testthis\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("ctime: time_t of 3 bytes"));
+    std::fs::remove_file(&db).unwrap();
+}
+
 #[test]
 fn get() {
     // URL
@@ -139,3 +359,643 @@ fn getrec() {
     println!("{}", sout);
     assert_eq!(sout.trim(), "some@email.com\nurl: https://facebook.com");
 }
+
+/// Copy the test database to a temporary file that can be modified
+fn tmpdb(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("pwx-test-{}-{}.psafe3", name, std::process::id()));
+    std::fs::copy("tests/test.psafe3", &path).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+/// Change the header and records of a test database
+fn edit_db<F>(db: &str, f: F) where F: FnOnce(&mut Vec<(u8, Value)>, &mut Vec<Vec<pwx::Field>>) {
+    let mut p = PwxReader::open(Path::new(db), b"test").unwrap();
+    let mut header = p.header().unwrap();
+    let mut records: Vec<_> = p.records().unwrap().map(|r| r.unwrap()).collect();
+    f(&mut header, &mut records);
+    p.save(&header, &records).unwrap();
+}
+
+#[test]
+fn recent() {
+    let db = tmpdb("recent");
+    let output = pwxrun!(&db, "recent");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    // get does not update the list unless asked to
+    let output = pwxrun!(&db, "get", "43fe1d0e-b65f-4e48-9abf-a1c5a1beeee8", "title");
+    assert!(output.status.success());
+    let output = pwxrun!(&db, "recent");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    let output = pwxrun!(&db, "get", "--update-recent", "43fe1d0e-b65f-4e48-9abf-a1c5a1beeee8", "title");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "facebook");
+    let output = pwxrun!(&db, "getrec", "--update-recent", "63a19136-46d9-4f75-827b-5312574233e8", "{title}");
+    assert!(output.status.success());
+
+    let output = pwxrun!(&db, "recent", "--fmt", "{title}\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "testthis\nfacebook\n");
+
+    // Filters still apply
+    let output = pwxrun!(&db, "recent", "--fmt", "{title}\n", "face");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "facebook\n");
+
+    let output = pwxrun!(&db, "get", "--update-recent", "43fe1d0e-b65f-4e48-9abf-a1c5a1beeee8", "title");
+    assert!(output.status.success());
+    let output = pwxrun!(&db, "recent", "--fmt", "{title}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "facebook\ntestthis\n");
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn saved_filters() {
+    let db = tmpdb("filters");
+    edit_db(&db, |header, _| {
+        let xml = concat!(r#"<filters><filter filtername="AGroup users">"#,
+                          r#"<filter_entry active="yes"><group><rule>EQ</rule><logic>and</logic>"#,
+                          r#"<test><string>agroup</string><case>0</case></test></group></filter_entry>"#,
+                          r#"<filter_entry active="yes"><user><rule>BE</rule><logic>and</logic>"#,
+                          r#"<test><string>test</string><case>1</case></test></user></filter_entry>"#,
+                          r#"</filter><filter filtername="Has URL">"#,
+                          r#"<filter_entry active="yes"><url><rule>PR</rule><logic>and</logic></url></filter_entry>"#,
+                          r#"</filter></filters>"#);
+        header.push((0x0b, Value::from(xml.as_bytes().to_vec())));
+    });
+
+    let output = pwxrun!(&db, "filters");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "AGroup users\nHas URL\n");
+
+    let output = pwxrun!(&db, "list", "--saved-filter", "AGroup users", "--fmt", "{title}\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "testthis\n");
+
+    let output = pwxrun!(&db, "list", "-S", "Has URL", "--fmt", "{title}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "facebook\ntestthis\n");
+
+    // Combined with other filters
+    let output = pwxrun!(&db, "list", "-S", "Has URL", "-T", "face", "--fmt", "{title}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "facebook\n");
+
+    let output = pwxrun!(&db, "list", "-S", "No such filter");
+    assert!(!output.status.success());
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn expiring() {
+    let output = pwxrun!("expiring", "--fmt", "{title} {days}\n");
+    assert!(output.status.success());
+    let sout = String::from_utf8_lossy(&output.stdout);
+    let titles: Vec<_> = sout.lines().map(|l| l.split(' ').next().unwrap()).collect();
+    assert_eq!(titles, vec!["testthis", "github", "facebook"]);
+    assert!(sout.lines().all(|l| l.split(' ').nth(1).unwrap().starts_with('-')));
+
+    let output = pwxrun!("expiring", "--expired", "--utc", "-T", "face");
+    let sout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(sout, "9PyDEwwoeWAdBP9fQzfKEF\t2017-05-11T11:52:52Z\t".to_owned()
+               + sout.split('\t').nth(2).unwrap() + "\tfacebook\n");
+
+    // Changing the password recomputes the expiry from the 90 day interval
+    let db = tmpdb("expiring");
+    edit_db(&db, |_, records| {
+        pwx::record::set_password(&mut records[2], b"newpass", &Utc::now()).unwrap();
+    });
+    let output = pwxrun!(&db, "expiring", "--within", "100", "--fmt", "{title} {days}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "github 89\n");
+    let output = pwxrun!(&db, "expiring", "--within", "30", "--fmt", "{title}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    let output = pwxrun!(&db, "expiring", "--within", "100", "--expired", "--fmt", "{title}\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "testthis\nfacebook\ngithub\n");
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn protect() {
+    let db = tmpdb("protect");
+    let output = pwxrun!(&db, "protect", "43fe1d0e-b65f-4e48-9abf-a1c5a1beeee8");
+    assert!(output.status.success());
+
+    let output = pwxrun!(&db, "list");
+    let sout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(sout.lines().filter(|l| l.ends_with(" (protected)")).count(), 1);
+    assert!(sout.contains("facebook [some@email.com] (protected)"));
+
+    let output = pwxrun!(&db, "get", "43fe1d0e-b65f-4e48-9abf-a1c5a1beeee8", "protected");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+
+    {
+        let mut p = PwxReader::open(Path::new(&db), b"test").unwrap();
+        let mut records: Vec<_> = p.records().unwrap().map(|r| r.unwrap()).collect();
+        assert!(pwx::record::set_password(&mut records[0], b"newpass", &Utc::now()).is_err());
+    }
+
+    let output = pwxrun!(&db, "unprotect", "9PyDEwwoeWAdBP9fQzfKEF");
+    assert!(output.status.success());
+    let output = pwxrun!(&db, "list");
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("(protected)"));
+
+    let output = pwxrun!(&db, "protect", "9PyDEwwoeWAdBP9fQzfKEX");
+    assert!(!output.status.success());
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn run() {
+    let db = tmpdb("run");
+    edit_db(&db, |_, records| {
+        let cmd = Value::from(b"echo \"$t: $u:$p\" '$g'; echo x$PWX_PASSWORD; cat".to_vec());
+        pwx::record::set_field(&mut records[2], pwx::Field::from(0x12, cmd)).unwrap();
+    });
+
+    let output = pwxrun!(&db, "run", "--dry-run", "DZhUE1kC7Tc7hNRY3ZXadS");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+               "echo \"github: devy:********\" 'AGroup'; echo x$PWX_PASSWORD; cat\n");
+
+    let output = pwxrun!(&db, "run", "--stdin", "DZhUE1kC7Tc7hNRY3ZXadS");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+               "github: devy:devy.password AGroup\nx\ndevy.password\n");
+
+    let output = pwxrun!(&db, "run", "9PyDEwwoeWAdBP9fQzfKEF");
+    assert!(!output.status.success());
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn autotype() {
+    let output = pwxrun!("autotype", "--emit", "json", "DZhUE1kC7Tc7hNRY3ZXadS");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+               "[{\"type\":\"text\",\"value\":\"devy\"},{\"type\":\"tab\"},\
+               {\"type\":\"text\",\"value\":\"devy.password\"},{\"type\":\"tab\"},{\"type\":\"enter\"}]\n");
+
+    let output = pwxrun!("autotype", "--emit", "wtype", "DZhUE1kC7Tc7hNRY3ZXadS");
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+               "#!/bin/sh\n\
+               printf '%s' 'devy' | wtype -d 10 -\n\
+               wtype -k 'Tab'\n\
+               printf '%s' 'devy.password' | wtype -d 10 -\n\
+               wtype -k 'Tab'\n\
+               wtype -k 'Return'\n");
+
+    let output = pwxrun!("autotype", "--emit", "sendkeys", "DZhUE1kC7Tc7hNRY3ZXadS");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn otp() {
+    let db = tmpdb("otp");
+    let output = pwxrun!(&db, "otp", "DZhUE1kC7Tc7hNRY3ZXadS");
+    assert!(!output.status.success());
+
+    let uri = "otpauth://totp/github?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8";
+    let output = pwxrun!(&db, "otp", "set", "DZhUE1kC7Tc7hNRY3ZXadS", uri);
+    assert!(output.status.success());
+    let output = pwxrun!(&db, "otp", "set", "DZhUE1kC7Tc7hNRY3ZXadS", "otpauth://totp/x?secret=1");
+    assert!(!output.status.success());
+
+    let totp = pwx::totp::Totp::from_uri(uri).unwrap();
+    let before = totp.code(Utc::now().timestamp());
+    let output = pwxrun!(&db, "otp", "DZhUE1kC7Tc7hNRY3ZXadS");
+    let after = totp.code(Utc::now().timestamp());
+    let code = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    assert!(code == before || code == after);
+
+    let output = pwxrun!(&db, "otp", "-o", "json", "DZhUE1kC7Tc7hNRY3ZXadS");
+    let otp: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(otp["id"], "DZhUE1kC7Tc7hNRY3ZXadS");
+    assert_eq!(otp["code"].as_str().unwrap().len(), 8);
+    assert!((1..=30).contains(&otp["remaining"].as_u64().unwrap()));
+
+    let output = pwxrun!(&db, "getrec", "DZhUE1kC7Tc7hNRY3ZXadS", "{title} {otp}");
+    let sout = String::from_utf8_lossy(&output.stdout);
+    assert!(sout.starts_with("github "));
+    assert_eq!(sout.trim().len(), "github ".len() + 8);
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn cards() {
+    let db = tmpdb("cards");
+    edit_db(&db, |_, records| {
+        let this_month = chrono::Local::now().format("%m/%Y").to_string();
+        let cards = [(0, "4111 1111 1111 1111", "01/2000"), (2, "1234 5678", this_month.as_str())];
+        for &(idx, number, expiry) in &cards {
+            let rec = &mut records[idx];
+            pwx::record::set_field(rec, pwx::Field::from(0x1c, Value::from(number.as_bytes().to_vec()))).unwrap();
+            pwx::record::set_field(rec, pwx::Field::from(0x1d, Value::from(expiry.as_bytes().to_vec()))).unwrap();
+            pwx::record::set_field(rec, pwx::Field::from(0x1e, Value::from(b"123".to_vec()))).unwrap();
+        }
+    });
+
+    let output = pwxrun!(&db, "cards", "--within", "40", "--fmt", "{title} {cc_number} {status} {cc_cvv}\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+               "facebook **** 1111 expired ****\ngithub **** 5678 expiring ****\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr),
+               "Warning: invalid card number in record DZhUE1kC7Tc7hNRY3ZXadS\n");
+
+    let output = pwxrun!(&db, "cards", "-o", "jsonl");
+    let sout = String::from_utf8_lossy(&output.stdout);
+    let cards: Vec<serde_json::Value> = sout.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(cards.len(), 2);
+    assert_eq!(cards[0]["status"], "expired");
+    assert_eq!(cards[0]["cc_expiry"], "01/2000");
+    assert!(cards[0].get("cc_number").is_none());
+
+    let output = pwxrun!(&db, "get", "9PyDEwwoeWAdBP9fQzfKEF", "cc_number");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "4111 1111 1111 1111\n");
+    let output = pwxrun!(&db, "getrec", "9PyDEwwoeWAdBP9fQzfKEF", "{cc_cvv}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "123");
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn open() {
+    let db = tmpdb("open");
+    edit_db(&db, |_, records| {
+        pwx::record::set_field(&mut records[0], pwx::Field::from(0x13, Value::from(vec![3, 0]))).unwrap();
+        pwx::record::set_field(&mut records[1], pwx::Field::from(0x13, Value::from(vec![9, 0]))).unwrap();
+    });
+    let open = |args: &[&str]| {
+        let mut binpath = current_exe().unwrap().parent().unwrap().parent().unwrap().to_path_buf();
+        binpath.push("pwx");
+        Command::new(&binpath)
+            .env("PWX_PASSWORD", "test")
+            .env("PWX_CLIPBOARD", "cat")
+            .env("PWX_BROWSER", "echo")
+            .arg(&db)
+            .arg("open")
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // github copies the password, and the username with shift
+    let output = open(&["DZhUE1kC7Tc7hNRY3ZXadS"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "devy.password");
+    let output = open(&["--shift", "DZhUE1kC7Tc7hNRY3ZXadS"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "devy");
+
+    let output = open(&["9PyDEwwoeWAdBP9fQzfKEF"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "https://facebook.com\n");
+
+    // testthis sends an email, but has no email address
+    let output = open(&["DJZsgbwbKNPkuQnB8btPZR"]);
+    assert!(!output.status.success());
+
+    // The view masks secrets, like show
+    edit_db(&db, |_, records| {
+        pwx::record::set_field(&mut records[2], pwx::Field::from(0x13, Value::from(vec![1, 0]))).unwrap();
+        pwx::record::set_field(&mut records[2], pwx::Field::from(0x1b, Value::from(b"JBSWY3DPEHPK3PXP".to_vec())))
+            .unwrap();
+    });
+    let output = open(&["DZhUE1kC7Tc7hNRY3ZXadS"]);
+    assert!(output.status.success());
+    let sout = String::from_utf8_lossy(&output.stdout);
+    assert!(sout.contains("github"));
+    assert!(!sout.contains("devy.password") && !sout.contains("JBSWY3DPEHPK3PXP"));
+    let output = open(&["--reveal", "DZhUE1kC7Tc7hNRY3ZXadS"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("JBSWY3DPEHPK3PXP"));
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn add() {
+    let db = tmpdb("add");
+    edit_db(&db, |header, _| {
+        header.retain(|&(typ, _)| typ != 0x02);
+        header.push((0x02, Value::from(b"B 4 1 S 3 \"defuser\" ".to_vec())));
+    });
+
+    let output = pwxrun!(&db, "add", "-T", "new", "-G", "Work", "-R", "https://example.com",
+                         "--email", "me@example.com", "--generate");
+    assert!(output.status.success());
+    let id = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    let output = pwxrun!(&db, "getrec", &id, "{group}|{title}|{username}|{url}|{email}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Work|new|defuser|https://example.com|me@example.com");
+    let output = pwxrun!(&db, "get", &id, "password");
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim().len(), 20);
+
+    let mut binpath = current_exe().unwrap().parent().unwrap().parent().unwrap().to_path_buf();
+    binpath.push("pwx");
+    let mut child = Command::new(&binpath)
+        .env("PWX_PASSWORD", "test")
+        .args([&db, "add", "-T", "other", "-U", "me", "--notes-file", "Cargo.toml"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"from stdin\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let id = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    let output = pwxrun!(&db, "getrec", &id, "{username} {password}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "me from stdin");
+    let output = pwxrun!(&db, "get", &id, "notes");
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("[package]"));
+
+    let output = pwxrun!(&db, "list");
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 5);
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn edit() {
+    let db = tmpdb("edit");
+    let output = pwxrun!(&db, "edit", "DZhUE1kC7Tc7hNRY3ZXadS", "--set", "url=https://github.com",
+                         "--set", "password=newpw", "--unset", "username");
+    assert!(output.status.success());
+    let output = pwxrun!(&db, "getrec", "DZhUE1kC7Tc7hNRY3ZXadS", "{url} {password} {username}|");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "https://github.com newpw |");
+    {
+        let mut p = PwxReader::open(Path::new(&db), b"test").unwrap();
+        let records: Vec<_> = p.records().unwrap().map(|r| r.unwrap()).collect();
+        let now = Utc::now();
+        for typ in &[0x08, 0x0c] {
+            let ts = pwx::record::time(&records[2], *typ).unwrap();
+            assert!((now - ts).num_seconds() < 60);
+        }
+    }
+
+    assert!(!pwxrun!(&db, "edit", "DZhUE1kC7Tc7hNRY3ZXadS", "--set", "nope=x").status.success());
+    assert!(!pwxrun!(&db, "edit", "DZhUE1kC7Tc7hNRY3ZXadS", "--set", "uuid=x").status.success());
+    assert!(!pwxrun!(&db, "edit", "DZhUE1kC7Tc7hNRY3ZXadS", "--set", "ctime=x").status.success());
+
+    let rundir = std::env::temp_dir().join(format!("pwx-test-edit-{}", std::process::id()));
+    std::fs::create_dir(&rundir).unwrap();
+    let edit = |editor: &str, recid: &str| {
+        let mut binpath = current_exe().unwrap().parent().unwrap().parent().unwrap().to_path_buf();
+        binpath.push("pwx");
+        Command::new(&binpath)
+            .env("PWX_PASSWORD", "test")
+            .env("XDG_RUNTIME_DIR", &rundir)
+            .env_remove("VISUAL")
+            .env("EDITOR", editor)
+            .args([&db, "edit", recid])
+            .output()
+            .unwrap()
+    };
+
+    let output = edit("stat -c %a", "DJZsgbwbKNPkuQnB8btPZR");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "600\n");
+
+    let output = edit("sed -i -e 's/^title: .*/title: edited/' -e '/^group:/d'", "DJZsgbwbKNPkuQnB8btPZR");
+    assert!(output.status.success());
+    let output = pwxrun!(&db, "getrec", "DJZsgbwbKNPkuQnB8btPZR", "{title} {username} {expiry_interval}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "edited testuser 90");
+    let output = pwxrun!(&db, "get", "DJZsgbwbKNPkuQnB8btPZR", "group");
+    assert!(!output.status.success());
+
+    let output = edit("sed -i -e 's/^title: .*/bogus/'", "DJZsgbwbKNPkuQnB8btPZR");
+    assert!(!output.status.success());
+    assert_eq!(std::fs::read_dir(&rundir).unwrap().count(), 0);
+    std::fs::remove_dir(&rundir).unwrap();
+
+    assert!(pwxrun!(&db, "protect", "DJZsgbwbKNPkuQnB8btPZR").status.success());
+    assert!(!pwxrun!(&db, "edit", "DJZsgbwbKNPkuQnB8btPZR", "--set", "title=x").status.success());
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn mv() {
+    let db = tmpdb("mv");
+    let output = pwxrun!(&db, "mv", "DZhUE1kC7Tc7hNRY3ZXadS", "DJZsgbwbKNPkuQnB8btPZR", "New.example\\.com");
+    assert!(output.status.success());
+    let output = pwxrun!(&db, "get", "DJZsgbwbKNPkuQnB8btPZR", "group");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "New.example\\.com\n");
+
+    let output = pwxrun!(&db, "mv", "-G", "New", "Top.Sub");
+    assert!(output.status.success());
+    let output = pwxrun!(&db, "get", "DZhUE1kC7Tc7hNRY3ZXadS", "group");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Top.Sub.example\\.com\n");
+    assert!(!pwxrun!(&db, "mv", "-G", "New", "Other").status.success());
+
+    let output = pwxrun!(&db, "mv", "DZhUE1kC7Tc7hNRY3ZXadS", "");
+    assert!(output.status.success());
+    assert!(!pwxrun!(&db, "get", "DZhUE1kC7Tc7hNRY3ZXadS", "group").status.success());
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn rm() {
+    let db = tmpdb("rm");
+    let output = pwxrun!(&db, "--long", "getrec", "DZhUE1kC7Tc7hNRY3ZXadS", "{uuid}");
+    let alias = format!("password=[[{}]]", String::from_utf8_lossy(&output.stdout).replace('-', ""));
+    assert!(pwxrun!(&db, "edit", "DJZsgbwbKNPkuQnB8btPZR", "--set", &alias).status.success());
+
+    // No confirmation, stdin is empty
+    let output = pwxrun!(&db, "rm", "DZhUE1kC7Tc7hNRY3ZXadS");
+    assert_eq!(output.status.code(), Some(255));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Alias"));
+    assert!(stderr.contains("github [devy]"));
+    assert!(pwxrun!(&db, "get", "DZhUE1kC7Tc7hNRY3ZXadS", "title").status.success());
+
+    let output = pwxrun!(&db, "rm", "--yes", "--unlink", "DZhUE1kC7Tc7hNRY3ZXadS");
+    assert!(output.status.success());
+    assert!(!pwxrun!(&db, "get", "DZhUE1kC7Tc7hNRY3ZXadS", "title").status.success());
+    let output = pwxrun!(&db, "get", "DJZsgbwbKNPkuQnB8btPZR", "password");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "devy.password\n");
+
+    assert!(pwxrun!(&db, "protect", "DJZsgbwbKNPkuQnB8btPZR").status.success());
+    assert!(!pwxrun!(&db, "rm", "-y", "DJZsgbwbKNPkuQnB8btPZR").status.success());
+
+    let mut binpath = current_exe().unwrap().parent().unwrap().parent().unwrap().to_path_buf();
+    binpath.push("pwx");
+    let mut child = Command::new(&binpath)
+        .env("PWX_PASSWORD", "test")
+        .args([&db, "rm", "9PyDEwwoeWAdBP9fQzfKEF"])
+        .stdin(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"y\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("facebook"));
+    let output = pwxrun!(&db, "list");
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 1);
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn show() {
+    let db = tmpdb("show");
+    edit_db(&db, |_, records| {
+        records[1].retain(|f| f.typ() != 0x0f);
+        records[1].push(pwx::Field::from(0x0f, Value::from(b"10500".to_vec())));
+        records[1].push(pwx::Field::from(0x30, Value::from(vec![0xca, 0xfe])));
+    });
+    let output = pwxrun!(&db, "--long", "getrec", "DZhUE1kC7Tc7hNRY3ZXadS", "{uuid}");
+    let alias = format!("password=[[{}]]", String::from_utf8_lossy(&output.stdout).replace('-', ""));
+    assert!(pwxrun!(&db, "edit", "DJZsgbwbKNPkuQnB8btPZR", "--set", &alias).status.success());
+
+    let output = pwxrun!(&db, "show", "DJZsgbwbKNPkuQnB8btPZR");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Alias of                    github [devy] DZhUE1kC7Tc7hNRY3ZXadS"));
+    assert!(stdout.contains("Field 0x30                  cafe"));
+    assert!(stdout.contains("Password history (kept, max 5)"));
+    assert!(stdout.contains("ago)  ********"));
+    assert!(!stdout.contains("devy.password"));
+
+    let output = pwxrun!(&db, "show", "--reveal", "--json", "DJZsgbwbKNPkuQnB8btPZR");
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["link"]["type"], "alias");
+    assert_eq!(json["link"]["title"], "github");
+    assert_eq!(json["history"]["entries"].as_array().unwrap().len(), 1);
+    let fields = json["fields"].as_array().unwrap();
+    let password = fields.iter().find(|f| f["name"] == "password").unwrap();
+    assert_eq!(password["value"], "devy.password");
+    let unknown = fields.iter().find(|f| f["type"] == 0x30).unwrap();
+    assert_eq!(unknown["value"], "cafe");
+    assert!(unknown["name"].is_null());
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn recid() {
+    let output = pwxrun!("get", "DZhU", "username");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "devy\n");
+    let output = pwxrun!("get", "AGroup/github", "username");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "devy\n");
+    let output = pwxrun!("get", "AGroup/testthis[testuser]", "title");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "testthis\n");
+    let output = pwxrun!("getrec", "43fe1d0e", "{title}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "facebook");
+
+    // Invalid ids are an error, not a panic
+    let output = pwxrun!("get", "not a record", "title");
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Unknown record: not a record\n");
+    assert!(!pwxrun!("get", "D", "title").status.success());
+
+    let db = tmpdb("recid");
+    let output = pwxrun!(&db, "add", "-T", "github", "-G", "AGroup", "-U", "other", "--generate");
+    assert!(output.status.success());
+    let output = pwxrun!(&db, "get", "AGroup/github", "username");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Ambiguous record id AGroup/github"));
+    assert!(stderr.contains("DZhUE1kC7Tc7hNRY3ZXadS AGroup/github[devy]"));
+    assert!(stderr.contains("AGroup/github[other]"));
+    let output = pwxrun!(&db, "get", "AGroup/github[other]", "username");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "other\n");
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn list_unicode() {
+    let db = tmpdb("unicode");
+    let records = [("Müller GmbH", "hans.müller", "Büro"),
+                   ("Ærø færge", "ærø", "Rejser"),
+                   ("Почта", "Иван", "Личное"),
+                   ("Τράπεζα", "ΓΙΏΡΓΟΣ", "Οικονομικά")];
+    for &(title, username, group) in &records {
+        let output = pwxrun!(&db, "add", "-T", title, "-U", username, "-G", group, "--generate");
+        assert!(output.status.success());
+    }
+    let count = |args: &[&str]| {
+        let mut binpath = current_exe().unwrap().parent().unwrap().parent().unwrap().to_path_buf();
+        binpath.push("pwx");
+        let output = Command::new(&binpath)
+            .env("PWX_PASSWORD", "test")
+            .arg(&db)
+            .arg("list")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).lines().count()
+    };
+
+    assert_eq!(count(&["-T", "MÜLLER"]), 1);
+    assert_eq!(count(&["-T", "muller"]), 0);
+    assert_eq!(count(&["-T", "muller", "--ignore-accents"]), 1);
+    assert_eq!(count(&["-U", "ÆRØ"]), 1);
+    assert_eq!(count(&["-G", "ЛИЧНОЕ"]), 1);
+    assert_eq!(count(&["-U", "γιώργος"]), 1);
+    assert_eq!(count(&["-A", "τραπεζα"]), 1);
+    assert_eq!(count(&["почта", "иван"]), 1);
+    assert_eq!(count(&["--where", "title~\"müller\" or group~\"BÜRO\""]), 1);
+    std::fs::remove_file(&db).unwrap();
+}
+
+fn pwxdiff(args: &[&str]) -> std::process::Output {
+    let pwxdiff = current_exe().unwrap().parent().unwrap().parent().unwrap().join("pwxdiff");
+    Command::new(&pwxdiff)
+        .env("PWX_PASSWORD", "test")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn pwxdiff_records() {
+    let output = pwxdiff(&["tests/test.psafe3", "tests/test.psafe3"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    let db = tmpdb("pwxdiff");
+    edit_db(&db, |_, records| {
+        records.remove(0);
+        pwx::record::set_field(&mut records[1], pwx::Field::from(0x03, Value::from(b"gitlab".to_vec()))).unwrap();
+        let now = chrono::TimeZone::with_ymd_and_hms(&Utc, 2024, 1, 2, 3, 4, 5).unwrap();
+        pwx::record::set_password(&mut records[1], b"secret\npass", &now).unwrap();
+        let mut new = pwx::record::new(&Utc::now()).unwrap();
+        new.push(pwx::Field::from(0x03, Value::from(b"new".to_vec())));
+        records.push(new);
+        records.push(vec![pwx::Field::from(0x03, Value::from(b"nouuid".to_vec()))]);
+    });
+    let output = pwxdiff(&["tests/test.psafe3", &db]);
+    assert_eq!(output.status.code(), Some(1));
+    let sout = String::from_utf8_lossy(&output.stdout);
+    let changes: Vec<_> = sout.lines().filter(|l| !l.starts_with(' ')).collect();
+    assert_eq!(changes.len(), 5);
+    assert_eq!(changes[0], "Changed header");
+    assert_eq!(changes[1], "Removed 9PyDEwwoeWAdBP9fQzfKEF facebook [some@email.com]");
+    assert_eq!(changes[2], "Changed DZhUE1kC7Tc7hNRY3ZXadS gitlab [devy]");
+    assert!(changes[3].starts_with("Added ") && changes[3].ends_with(" new []"));
+    assert_eq!(changes[4], "Added (no uuid) nouuid []");
+    assert!(sout.starts_with("Changed header\n  mtime: 2017-02-10T11:52:52Z -> "));
+    assert!(sout.contains("Changed DZhUE1kC7Tc7hNRY3ZXadS gitlab [devy]\n  title: github -> gitlab\n"));
+    assert!(sout.contains("  ptime: (none) -> 2024-01-02 03:04:05 UTC\n"));
+    assert!(sout.contains("  password changed, ptime (none) -> 2024-01-02 03:04:05 UTC\n"));
+    assert!(!sout.contains("devy.password"));
+
+    let output = pwxdiff(&["--reveal", "tests/test.psafe3", &db]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("  password: devy.password -> secret\\npass\n"));
+
+    let output = pwxdiff(&["-q", "tests/test.psafe3", &db]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    let output = pwxdiff(&["--output", "json", "tests/test.psafe3", &db]);
+    assert_eq!(output.status.code(), Some(1));
+    let changes: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(changes["header"][0]["field"], "mtime");
+    assert_eq!(changes["header"][0]["old"], "2017-02-10T11:52:52Z");
+    assert_eq!(changes["removed"][0]["id"], "9PyDEwwoeWAdBP9fQzfKEF");
+    assert_eq!(changes["added"].as_array().unwrap().len(), 2);
+    assert_eq!(changes["added"][1]["title"], "nouuid");
+    let changed = &changes["changed"][0];
+    assert_eq!(changed["id"], "DZhUE1kC7Tc7hNRY3ZXadS");
+    assert_eq!(changed["fields"][0], serde_json::json!({"field": "title", "old": "github", "new": "gitlab"}));
+    assert!(changed["fields"].as_array().unwrap().contains(&serde_json::json!({"field": "password"})));
+    assert!(changed["fields"].as_array().unwrap()
+            .contains(&serde_json::json!({"field": "ptime", "old": null, "new": "2024-01-02T03:04:05Z"})));
+
+    assert_eq!(pwxdiff(&["tests/test.psafe3", "tests/nosuchfile.psafe3"]).status.code(), Some(2));
+    assert_eq!(pwxdiff(&["-o", "xml", "tests/test.psafe3", &db]).status.code(), Some(2));
+    assert_eq!(pwxdiff(&["tests/test.psafe3"]).status.code(), Some(2));
+    std::fs::remove_file(&db).unwrap();
+}
@@ -60,3 +60,63 @@ fn test_record_iter() {
     }
     assert_eq!(count, count2);
 }
+
+#[test]
+fn test_save() {
+    let path = std::env::temp_dir().join(format!("pwx-test-save-{}.psafe3", std::process::id()));
+    std::fs::copy("tests/test.psafe3", &path).unwrap();
+
+    let mut p = PwxReader::open(&path, b"test").unwrap();
+    let header = p.header().unwrap();
+    let records: Vec<_> = p.records().unwrap().map(|r| r.unwrap()).collect();
+    p.save(&header, &records).unwrap();
+
+    // The reader is still usable after saving
+    assert!(p.authenticate().is_ok());
+
+    let mut p = PwxReader::open(&path, b"test").unwrap();
+    assert!(p.authenticate().is_ok());
+    let saved: Vec<_> = p.records().unwrap().map(|r| r.unwrap()).collect();
+    assert!(records == saved);
+
+    // Except for the save time and application fields the header is unchanged
+    let saved_header = p.header().unwrap();
+    assert_eq!(header.len(), saved_header.len());
+    for (a, b) in header.iter().zip(saved_header.iter()) {
+        assert_eq!(a.0, b.0);
+        if a.0 != 0x04 && a.0 != 0x06 {
+            assert_eq!(a.1, b.1);
+        }
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_save_tmp() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("pwx-test-savetmp-{}", std::process::id()));
+    std::fs::create_dir(&dir).unwrap();
+    let path = dir.join("db.psafe3");
+    std::fs::copy("tests/test.psafe3", &path).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+    let mut p = PwxReader::open(&path, b"test").unwrap();
+    let header = p.header().unwrap();
+    let records: Vec<_> = p.records().unwrap().map(|r| r.unwrap()).collect();
+
+    // A file left by an interrupted save does not stop saving, and is
+    // not overwritten
+    let stale = dir.join("db.psafe3.tmp");
+    std::fs::write(&stale, b"stale").unwrap();
+    p.save(&header, &records).unwrap();
+    p.save(&header, &records).unwrap();
+    assert_eq!(std::fs::read(&stale).unwrap(), b"stale");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    let mut p = PwxReader::open(&path, b"test").unwrap();
+    assert!(p.authenticate().is_ok());
+    std::fs::remove_dir_all(&dir).unwrap();
+}